@@ -0,0 +1,11 @@
+(module
+  (type $t0 (func (param i32 i32) (result i32)))
+  (import "env" "sum" (func $sum (type $t0)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "\01\02\03\04")
+  (func $sum_bytes (export "sum_bytes") (result i32)
+    i32.const 0
+    i32.const 4
+    call $sum
+  )
+)
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+plugged::manifest!(host_api = "^0.1");
+
+#[derive(Deserialize, Serialize)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[plugged::export]
+fn add(left: i32, right: i32) -> i32 {
+    left + right
+}
+
+/// Aliases of numbers are passed as numbers too.
+type Count = i32;
+
+#[plugged::export]
+fn double(count: Count) -> Count {
+    count * 2
+}
+
+#[plugged::export]
+fn greet(name: &str) -> String {
+    format!("Hello, {name}")
+}
+
+#[plugged::export]
+fn translate(point: Point, by: i32) -> Point {
+    Point {
+        x: point.x + by,
+        y: point.y + by,
+    }
+}
+
+/// Takes its input as a single value, as `Plugin::call_serde` passes it.
+#[plugged::export]
+fn offset((point, by): (Point, i32)) -> Point {
+    translate(point, by)
+}
+
+#[plugged::export]
+fn checked_div(a: i32, b: i32) -> Result<i32, String> {
+    a.checked_div(b).ok_or_else(|| "division by zero".into())
+}
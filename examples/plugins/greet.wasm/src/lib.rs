@@ -0,0 +1,23 @@
+use plugged::abi;
+
+plugged::import! {
+    "env" {
+        fn name() -> String;
+        fn log(message: &str);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn greet(greeting: i64) -> i64 {
+    plugged::host::set_panic_hook();
+    let greeting = unsafe { abi::borrow_str(greeting) };
+    let message = format!("{greeting}, {}", name());
+    log(&message);
+    abi::give(message.into_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn fail() {
+    plugged::host::set_panic_hook();
+    panic!("nothing to see here");
+}
@@ -0,0 +1,9 @@
+(module
+  (type $t0 (func (param i32) (result i32)))
+  (import "env" "double" (func $double (type $t0)))
+  (func $quadruple (export "quadruple") (type $t0) (param $p0 i32) (result i32)
+    local.get $p0
+    call $double
+    call $double
+  )
+)
@@ -0,0 +1,21 @@
+(module
+  (func (export "spin")
+    (loop $forever
+      br $forever)
+  )
+  (func (export "count") (param $n i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        local.get $i
+        local.get $n
+        i32.ge_s
+        br_if $done
+        local.get $i
+        i32.const 1
+        i32.add
+        local.set $i
+        br $next))
+    local.get $i
+  )
+)
@@ -0,0 +1,8 @@
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "hello")
+  (func $load (export "load") (param $p0 i32) (result i32)
+    local.get $p0
+    i32.load
+  )
+)
@@ -0,0 +1,13 @@
+(module
+  (import "plugged" "panic" (func $panic (param i64)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "boom")
+  (func (export "fail")
+    i64.const 4
+    call $panic
+    unreachable
+  )
+  (func (export "trap")
+    unreachable
+  )
+)
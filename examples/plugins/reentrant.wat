@@ -0,0 +1,18 @@
+(module
+  (type $t0 (func (param i32) (result i32)))
+  (import "env" "callback" (func $callback (type $t0)))
+  (func $outer (export "outer") (type $t0) (param $p0 i32) (result i32)
+    local.get $p0
+    call $callback
+  )
+  (func $inner (export "inner") (type $t0) (param $p0 i32) (result i32)
+    local.get $p0
+    i32.const 1
+    i32.add
+  )
+  (func $spin (export "spin") (param $p0 i32)
+    (loop $spin
+      br $spin
+    )
+  )
+)
@@ -0,0 +1,91 @@
+(module
+  (import "plugged" "panic" (func $panic (param i64)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "Hello, ")
+  (global $heap (mut i32) (i32.const 1024))
+  (global $freed (mut i32) (i32.const 0))
+  (func $alloc (export "alloc") (param $len i32) (result i32)
+    global.get $heap
+    global.get $heap
+    local.get $len
+    i32.add
+    global.set $heap
+  )
+  (func $dealloc (export "dealloc") (param $ptr i32) (param $len i32)
+    global.get $freed
+    i32.const 1
+    i32.add
+    global.set $freed
+  )
+  (func $freed (export "freed") (result i32)
+    global.get $freed
+  )
+  (func $greet (export "greet") (param $name i64) (result i64)
+    (local $ptr i32) (local $len i32) (local $out i32)
+    local.get $name
+    i64.const 32
+    i64.shr_u
+    i32.wrap_i64
+    local.set $ptr
+    local.get $name
+    i32.wrap_i64
+    local.set $len
+    local.get $len
+    i32.const 7
+    i32.add
+    call $alloc
+    local.set $out
+    local.get $out
+    i32.const 0
+    i32.const 7
+    memory.copy
+    local.get $out
+    i32.const 7
+    i32.add
+    local.get $ptr
+    local.get $len
+    memory.copy
+    local.get $out
+    i64.extend_i32_u
+    i64.const 32
+    i64.shl
+    local.get $len
+    i32.const 7
+    i32.add
+    i64.extend_i32_u
+    i64.or
+  )
+  (func $echo (export "echo") (param $input i64) (result i64)
+    (local $len i32) (local $out i32)
+    local.get $input
+    i32.wrap_i64
+    local.set $len
+    local.get $len
+    call $alloc
+    local.set $out
+    local.get $out
+    local.get $input
+    i64.const 32
+    i64.shr_u
+    i32.wrap_i64
+    local.get $len
+    memory.copy
+    local.get $out
+    i64.extend_i32_u
+    i64.const 32
+    i64.shl
+    local.get $len
+    i64.extend_i32_u
+    i64.or
+  )
+  (func $fail (export "fail") (param $input i64)
+    local.get $input
+    call $panic
+    unreachable
+  )
+  (func $spin (export "spin") (param $input i64)
+    (loop $spin
+      br $spin
+    )
+  )
+)
@@ -0,0 +1,61 @@
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_sizes_get"
+    (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_sizes_get"
+    (func $environ_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_get"
+    (func $fd_prestat_get (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 64) "hello\n")
+  (data (i32.const 80) "oops\n")
+  ;; Writes `len` bytes at `ptr` to `fd` through an iovec at 0.
+  (func $write (param $fd i32) (param $ptr i32) (param $len i32) (result i32)
+    i32.const 0
+    local.get $ptr
+    i32.store
+    i32.const 4
+    local.get $len
+    i32.store
+    local.get $fd
+    i32.const 0
+    i32.const 1
+    i32.const 8
+    call $fd_write
+  )
+  (func (export "hello") (result i32)
+    i32.const 1
+    i32.const 64
+    i32.const 6
+    call $write
+    i32.const 2
+    i32.const 80
+    i32.const 5
+    call $write
+    i32.or
+  )
+  ;; Number of arguments, including the program name.
+  (func (export "args") (result i32)
+    i32.const 16
+    i32.const 20
+    call $args_sizes_get
+    drop
+    i32.const 16
+    i32.load
+  )
+  (func (export "envs") (result i32)
+    i32.const 16
+    i32.const 20
+    call $environ_sizes_get
+    drop
+    i32.const 16
+    i32.load
+  )
+  ;; Errno of looking up the first preopened directory.
+  (func (export "preopened") (result i32)
+    i32.const 3
+    i32.const 16
+    call $fd_prestat_get
+  )
+)
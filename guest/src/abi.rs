@@ -0,0 +1,276 @@
+//! Fat pointers and the buffer ownership rules shared with the host.
+//!
+//! Strings and byte buffers cross the boundary as a single `i64` holding
+//! `ptr << 32 | len`. A zero length never refers to an allocation.
+//!
+//! - Arguments of exported functions are lent by the host for the duration of
+//!   the call: [`borrow_bytes`], [`borrow_str`].
+//! - Results of exported functions are given to the host, which frees them
+//!   through `dealloc`: [`give`].
+//! - Arguments of host imports are lent to the host: [`lend`].
+//! - Results of host imports are allocated by the host through `alloc` and
+//!   owned by the plugin afterwards: [`take`].
+
+/// Version of the calling convention, which the host checks before loading
+/// the plugin. Raised whenever the convention changes incompatibly.
+pub const VERSION: u32 = 1;
+
+pub fn pack(ptr: u32, len: u32) -> i64 {
+    ((u64::from(ptr) << 32) | u64::from(len)) as i64
+}
+
+pub fn unpack(fat: i64) -> (u32, u32) {
+    ((fat as u64 >> 32) as u32, fat as u32)
+}
+
+/// Borrows a buffer lent by the host.
+///
+/// # Safety
+///
+/// `fat` must describe a live buffer in this plugin's memory, such as an
+/// argument of the exported function being called.
+pub unsafe fn borrow_bytes<'a>(fat: i64) -> &'a [u8] {
+    match unpack(fat) {
+        (_, 0) => &[],
+        (ptr, len) => std::slice::from_raw_parts(ptr as usize as *const u8, len as usize),
+    }
+}
+
+/// Borrows a string lent by the host.
+///
+/// # Safety
+///
+/// Same as [`borrow_bytes`]. Panics if the buffer is not UTF-8.
+pub unsafe fn borrow_str<'a>(fat: i64) -> &'a str {
+    std::str::from_utf8(borrow_bytes(fat)).expect("host passed a string that is not UTF-8")
+}
+
+/// Hands `bytes` over to the host, which releases them once copied out.
+pub fn give(bytes: Vec<u8>) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+    let len = bytes.len() as u32;
+    let ptr = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+    pack(ptr as usize as u32, len)
+}
+
+/// Lends `bytes` to the host for the duration of an import call.
+pub fn lend(bytes: &[u8]) -> i64 {
+    pack(bytes.as_ptr() as usize as u32, bytes.len() as u32)
+}
+
+/// Takes ownership of a buffer the host allocated through `alloc`.
+///
+/// # Safety
+///
+/// `fat` must describe a buffer allocated by `alloc` and not owned by anything
+/// else, such as the result of a host import.
+pub unsafe fn take(fat: i64) -> Vec<u8> {
+    match unpack(fat) {
+        (_, 0) => Vec::new(),
+        (ptr, len) => Vec::from_raw_parts(ptr as usize as *mut u8, len as usize, len as usize),
+    }
+}
+
+/// Arguments of functions exported with [`export`](crate::export) that are
+/// passed without a codec. Other arguments must implement
+/// `serde::Deserialize`.
+pub trait FromHost {
+    type Raw;
+
+    /// # Safety
+    ///
+    /// `raw` must be an argument of the exported function being called, see
+    /// [`borrow_bytes`].
+    unsafe fn from_host(raw: Self::Raw) -> Self;
+}
+
+/// Results of functions exported with [`export`](crate::export) that are
+/// passed without a codec. Other results must implement `serde::Serialize`.
+pub trait IntoHost {
+    type Raw;
+
+    fn into_host(self) -> Self::Raw;
+}
+
+/// Values passed to host imports declared with [`import!`](crate::import).
+pub trait HostArg {
+    type Raw;
+
+    fn lend(&self) -> Self::Raw;
+}
+
+/// Values returned from host imports declared with [`import!`](crate::import).
+pub trait HostRet {
+    type Raw;
+
+    /// # Safety
+    ///
+    /// `raw` must be the result of the host import, see [`take`].
+    unsafe fn take(raw: Self::Raw) -> Self;
+}
+
+macro_rules! impl_number {
+    ($($ty:ty),*) => {
+        $(
+            impl HostArg for $ty {
+                type Raw = $ty;
+
+                fn lend(&self) -> $ty {
+                    *self
+                }
+            }
+
+            impl HostRet for $ty {
+                type Raw = $ty;
+
+                unsafe fn take(raw: $ty) -> $ty {
+                    raw
+                }
+            }
+
+            impl FromHost for $ty {
+                type Raw = $ty;
+
+                unsafe fn from_host(raw: $ty) -> $ty {
+                    raw
+                }
+            }
+
+            impl IntoHost for $ty {
+                type Raw = $ty;
+
+                fn into_host(self) -> $ty {
+                    self
+                }
+            }
+        )*
+    };
+}
+
+impl_number!(i32, u32, i64, u64, f32, f64);
+
+impl HostArg for &[u8] {
+    type Raw = i64;
+
+    fn lend(&self) -> i64 {
+        lend(self)
+    }
+}
+
+impl HostArg for Vec<u8> {
+    type Raw = i64;
+
+    fn lend(&self) -> i64 {
+        lend(self)
+    }
+}
+
+impl HostArg for &str {
+    type Raw = i64;
+
+    fn lend(&self) -> i64 {
+        lend(self.as_bytes())
+    }
+}
+
+impl HostArg for String {
+    type Raw = i64;
+
+    fn lend(&self) -> i64 {
+        lend(self.as_bytes())
+    }
+}
+
+impl HostRet for Vec<u8> {
+    type Raw = i64;
+
+    unsafe fn take(raw: i64) -> Self {
+        take(raw)
+    }
+}
+
+impl HostRet for String {
+    type Raw = i64;
+
+    unsafe fn take(raw: i64) -> Self {
+        String::from_utf8(take(raw)).expect("host returned a string that is not UTF-8")
+    }
+}
+
+impl FromHost for &[u8] {
+    type Raw = i64;
+
+    unsafe fn from_host(raw: i64) -> Self {
+        borrow_bytes(raw)
+    }
+}
+
+impl FromHost for Vec<u8> {
+    type Raw = i64;
+
+    unsafe fn from_host(raw: i64) -> Self {
+        borrow_bytes(raw).to_vec()
+    }
+}
+
+impl FromHost for &str {
+    type Raw = i64;
+
+    unsafe fn from_host(raw: i64) -> Self {
+        borrow_str(raw)
+    }
+}
+
+impl FromHost for String {
+    type Raw = i64;
+
+    unsafe fn from_host(raw: i64) -> Self {
+        borrow_str(raw).to_owned()
+    }
+}
+
+impl IntoHost for &[u8] {
+    type Raw = i64;
+
+    fn into_host(self) -> i64 {
+        give(self.to_vec())
+    }
+}
+
+impl IntoHost for Vec<u8> {
+    type Raw = i64;
+
+    fn into_host(self) -> i64 {
+        give(self)
+    }
+}
+
+impl IntoHost for &str {
+    type Raw = i64;
+
+    fn into_host(self) -> i64 {
+        give(self.as_bytes().to_vec())
+    }
+}
+
+impl IntoHost for String {
+    type Raw = i64;
+
+    fn into_host(self) -> i64 {
+        give(self.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fat_pointers() {
+        assert_eq!(unpack(pack(1024, 7)), (1024, 7));
+        assert_eq!(unpack(pack(u32::MAX, u32::MAX)), (u32::MAX, u32::MAX));
+        assert_eq!(pack(0, 0), 0);
+    }
+}
@@ -0,0 +1,22 @@
+//! Encodings shared with the host, see [`plugged_codec`], and the glue
+//! moving encoded values across the calling convention.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use plugged_codec::*;
+
+/// Decodes an argument lent by the host.
+///
+/// # Safety
+///
+/// See [`borrow_bytes`](crate::abi::borrow_bytes).
+pub unsafe fn decode<C: Codec, T: DeserializeOwned>(codec: &C, fat: i64) -> T {
+    let bytes = crate::abi::borrow_bytes(fat);
+    codec.decode(bytes).expect("failed to decode plugin input")
+}
+
+/// Encodes a result and gives it to the host.
+pub fn encode<C: Codec, T: Serialize + ?Sized>(codec: &C, value: &T) -> i64 {
+    let bytes = codec.encode(value).expect("failed to encode plugin output");
+    crate::abi::give(bytes)
+}
@@ -0,0 +1,69 @@
+//! Calls into the host.
+
+use std::sync::Once;
+
+/// Declares host imports with the same argument and result types the host
+/// side accepts, lending buffers and taking ownership of returned ones.
+///
+/// ```ignore
+/// plugged_guest::import! {
+///     "env" {
+///         fn log(message: &str);
+///         fn lookup(key: &str) -> String;
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! import {
+    ($module:literal {
+        $(
+            $(#[$attr:meta])*
+            $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;
+        )*
+    }) => {
+        $(
+            $(#[$attr])*
+            $vis fn $name($($arg: $ty),*) $(-> $ret)? {
+                #[link(wasm_import_module = $module)]
+                extern "C" {
+                    #[link_name = stringify!($name)]
+                    fn import($($arg: <$ty as $crate::abi::HostArg>::Raw),*)
+                        $(-> <$ret as $crate::abi::HostRet>::Raw)?;
+                }
+                unsafe {
+                    $(<$ret as $crate::abi::HostRet>::take)?(
+                        import($($crate::abi::HostArg::lend(&$arg)),*)
+                    )
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "plugged")]
+extern "C" {
+    #[link_name = "panic"]
+    fn host_panic(message: i64);
+}
+
+/// Forwards panic messages to the host, which reports them as
+/// `PluginError::Panic` instead of a bare trap.
+///
+/// Cheap to call repeatedly; the hook is installed once.
+pub fn set_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        std::panic::set_hook(Box::new(|info| report_panic(&info.to_string())));
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn report_panic(message: &str) {
+    unsafe { host_panic(crate::abi::lend(message.as_bytes())) }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn report_panic(message: &str) {
+    eprintln!("{message}");
+}
@@ -0,0 +1,31 @@
+//! Guest-side support for writing [plugged](https://github.com/geekylthyosaur/plugged)
+//! plugins.
+//!
+//! Linking this crate into a `cdylib` exports the `alloc`/`dealloc` pair the
+//! host needs to pass buffers, and provides helpers for the rest of the
+//! calling convention:
+//!
+//! - [`abi`] converts between fat pointers and Rust buffers,
+//! - [`codec`] encodes structured values the same way the host does,
+//! - [`host`] calls host imports and reports panics back to the host,
+//! - [`export`] generates all of the above for an ordinary Rust function and
+//!   describes the plugin to the host from its `Cargo.toml`,
+//! - [`manifest!`] adds the host API version the plugin requires.
+//!
+//! ```ignore
+//! use plugged_guest as plugged;
+//!
+//! #[plugged::export]
+//! fn greet(name: &str) -> String {
+//!     format!("Hello, {name}")
+//! }
+//! ```
+
+pub mod abi;
+pub mod codec;
+pub mod host;
+mod memory;
+#[doc(hidden)]
+pub mod shim;
+
+pub use plugged_macros::{export, manifest};
@@ -0,0 +1,41 @@
+//! Allocator exports used by the host to pass buffers into the plugin.
+
+#[cfg(target_arch = "wasm32")]
+use std::alloc::Layout;
+
+/// Allocates `len` bytes the host writes an argument or an import result to.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    if len <= 0 {
+        return 0;
+    }
+    let layout = Layout::array::<u8>(len as usize).expect("allocation too large");
+    let ptr = unsafe { std::alloc::alloc(layout) };
+    if ptr.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    ptr as i32
+}
+
+/// Frees a buffer obtained from [`alloc`] or handed out by [`give`](crate::abi::give).
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a live allocation made by this module.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(ptr: i32, len: i32) {
+    if len <= 0 {
+        return;
+    }
+    let layout = Layout::array::<u8>(len as usize).expect("allocation too large");
+    std::alloc::dealloc(ptr as *mut u8, layout);
+}
+
+/// Declares [`VERSION`](crate::abi::VERSION) to the host. Kept next to the
+/// allocator exports, so every plugin linking them declares it too.
+#[cfg(target_arch = "wasm32")]
+#[link_section = "plugged.abi"]
+#[used]
+static ABI_VERSION: [u8; 4] = crate::abi::VERSION.to_le_bytes();
@@ -0,0 +1,108 @@
+//! Glue used by the code [`export`](crate::export) generates, not meant to be
+//! used directly.
+//!
+//! Argument and result types implementing [`FromHost`] and [`IntoHost`] are
+//! passed as they are, every other type goes through the codec. The choice is
+//! made by [`RawArg::RAW`] and [`RawRet::RAW`]: the inherent constant exists
+//! only when the bound holds, otherwise the one of [`Encoded`] is used. This
+//! only works for concrete types, which is all exported functions take.
+
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    abi::{FromHost, IntoHost},
+    codec::{self, Codec},
+};
+
+/// Whether `T` is passed to exported functions as it is.
+pub struct RawArg<T>(PhantomData<T>);
+
+impl<T: FromHost> RawArg<T> {
+    pub const RAW: bool = true;
+}
+
+/// Whether `T` is returned from exported functions as it is.
+pub struct RawRet<T>(PhantomData<T>);
+
+impl<T: IntoHost> RawRet<T> {
+    pub const RAW: bool = true;
+}
+
+/// Fallback of [`RawArg::RAW`] and [`RawRet::RAW`] for types going through
+/// the codec.
+pub trait Encoded {
+    const RAW: bool = false;
+}
+
+impl<T> Encoded for RawArg<T> {}
+
+impl<T> Encoded for RawRet<T> {}
+
+/// Argument of type `T`, passed as it is if `RAW`.
+pub struct Arg<T, const RAW: bool>(PhantomData<T>);
+
+/// Result of type `T`, returned as it is if `RAW`.
+pub struct Ret<T, const RAW: bool>(PhantomData<T>);
+
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be an argument of an exported function",
+    note = "arguments must implement `FromHost` or `serde::Deserialize`"
+)]
+pub trait Param {
+    type Raw;
+    type Value;
+
+    /// # Safety
+    ///
+    /// See [`FromHost::from_host`].
+    unsafe fn from_host<C: Codec>(raw: Self::Raw, codec: &C) -> Self::Value;
+}
+
+impl<T: FromHost> Param for Arg<T, true> {
+    type Raw = T::Raw;
+    type Value = T;
+
+    unsafe fn from_host<C: Codec>(raw: T::Raw, _: &C) -> T {
+        T::from_host(raw)
+    }
+}
+
+impl<T: DeserializeOwned> Param for Arg<T, false> {
+    type Raw = i64;
+    type Value = T;
+
+    unsafe fn from_host<C: Codec>(raw: i64, codec: &C) -> T {
+        codec::decode(codec, raw)
+    }
+}
+
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be the result of an exported function",
+    note = "results must implement `IntoHost` or `serde::Serialize`"
+)]
+pub trait Output {
+    type Raw;
+    type Value;
+
+    fn into_host<C: Codec>(value: Self::Value, codec: &C) -> Self::Raw;
+}
+
+impl<T: IntoHost> Output for Ret<T, true> {
+    type Raw = T::Raw;
+    type Value = T;
+
+    fn into_host<C: Codec>(value: T, _: &C) -> T::Raw {
+        value.into_host()
+    }
+}
+
+impl<T: Serialize> Output for Ret<T, false> {
+    type Raw = i64;
+    type Value = T;
+
+    fn into_host<C: Codec>(value: T, codec: &C) -> i64 {
+        codec::encode(codec, &value)
+    }
+}
@@ -0,0 +1,158 @@
+use proc_macro2::{Span, TokenStream};
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse::{Parse, ParseStream},
+    spanned::Spanned,
+    FnArg, Ident, ItemFn, Pat, Path, ReturnType, Token, Type,
+};
+
+const SECTION: &str = "plugged.signatures";
+
+pub struct Args {
+    codec: Option<Path>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut codec = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "codec" => codec = Some(input.parse()?),
+                _ => return Err(syn::Error::new(key.span(), "expected `codec = path`")),
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(Self { codec })
+    }
+}
+
+pub fn expand(args: Args, item: ItemFn) -> syn::Result<TokenStream> {
+    let sig = &item.sig;
+    if let Some(token) = &sig.asyncness {
+        return Err(syn::Error::new(
+            token.span(),
+            "exported functions can't be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "exported functions can't be generic",
+        ));
+    }
+
+    let krate = guest_crate();
+    let codec = match args.codec {
+        Some(codec) => codec.into_token_stream(),
+        None => quote!(#krate::codec::Json),
+    };
+
+    let name = &sig.ident;
+    let mut params = Vec::new();
+    let mut raw_params = Vec::new();
+    let mut conversions = Vec::new();
+    let mut call_args = Vec::new();
+    for (i, input) in sig.inputs.iter().enumerate() {
+        let FnArg::Typed(arg) = input else {
+            return Err(syn::Error::new(
+                input.span(),
+                "exported functions can't take `self`",
+            ));
+        };
+        let ty = &*arg.ty;
+        let raw = format_ident!("arg{i}");
+        let label = match &*arg.pat {
+            Pat::Ident(pat) => pat.ident.to_string(),
+            _ => format!("arg{i}"),
+        };
+        params.push((label, type_name(ty)));
+
+        let arg = quote!(#krate::shim::Arg<#ty, { #krate::shim::RawArg::<#ty>::RAW }>);
+        raw_params.push(quote!(#raw: <#arg as #krate::shim::Param>::Raw));
+        conversions.push(quote! {
+            let #raw = unsafe { <#arg as #krate::shim::Param>::from_host(#raw, &#codec) };
+        });
+        call_args.push(raw);
+    }
+
+    let (result, raw_result, ret) = match &sig.output {
+        ReturnType::Default => (None, quote!(), quote!(result)),
+        ReturnType::Type(_, ty) => {
+            let output = quote!(#krate::shim::Ret<#ty, { #krate::shim::RawRet::<#ty>::RAW }>);
+            (
+                Some(type_name(ty)),
+                quote!(-> <#output as #krate::shim::Output>::Raw),
+                quote!(<#output as #krate::shim::Output>::into_host(result, &#codec)),
+            )
+        }
+    };
+
+    let signature = serde_json::json!({
+        "name": name.to_string(),
+        "params": params,
+        "result": result,
+    });
+    let signature = format!("{signature}\n");
+    let signature_len = signature.len();
+    let signature = syn::LitByteStr::new(signature.as_bytes(), Span::call_site());
+    let export_name = name.to_string();
+    let manifest = crate::manifest::record(None);
+
+    Ok(quote! {
+        #item
+
+        #manifest
+
+        const _: () = {
+            #[allow(unused_imports)]
+            use #krate::shim::Encoded as _;
+
+            #[export_name = #export_name]
+            extern "C" fn shim(#(#raw_params),*) #raw_result {
+                #krate::host::set_panic_hook();
+                #(#conversions)*
+                let result = #name(#(#call_args),*);
+                #ret
+            }
+
+            #[cfg_attr(target_arch = "wasm32", link_section = #SECTION)]
+            #[used]
+            static SIGNATURE: [u8; #signature_len] = *#signature;
+        };
+    })
+}
+
+/// Renders `ty` the way it is written, without the spacing `quote` adds.
+fn type_name(ty: &Type) -> String {
+    let tokens = ty.to_token_stream().to_string();
+    let mut name = String::with_capacity(tokens.len());
+    let mut chars = tokens.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ' ' {
+            let prev = name.chars().last().unwrap_or(' ');
+            let next = chars.peek().copied().unwrap_or(' ');
+            let word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+            if word(prev) && word(next) {
+                name.push(' ');
+            }
+            continue;
+        }
+        name.push(c);
+    }
+    name
+}
+
+fn guest_crate() -> TokenStream {
+    match crate_name("plugged-guest") {
+        Ok(FoundCrate::Name(name)) => {
+            let name = Ident::new(&name, Span::call_site());
+            quote!(::#name)
+        }
+        _ => quote!(::plugged_guest),
+    }
+}
@@ -0,0 +1,128 @@
+use proc_macro2::{Span, TokenStream};
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::{format_ident, quote};
+use syn::{spanned::Spanned, FnArg, Ident, ItemTrait, Pat, ReturnType, TraitItem};
+
+pub fn expand(mut item: ItemTrait) -> syn::Result<TokenStream> {
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "interfaces can't be generic",
+        ));
+    }
+
+    let krate = host_crate();
+    let vis = &item.vis;
+    let name = &item.ident;
+    let proxy = format_ident!("{name}Proxy");
+
+    let mut checks = Vec::new();
+    let mut methods = Vec::new();
+    for trait_item in &mut item.items {
+        let TraitItem::Fn(method) = trait_item else {
+            return Err(syn::Error::new(
+                trait_item.span(),
+                "interfaces may only declare methods",
+            ));
+        };
+        if let Some(body) = &method.default {
+            return Err(syn::Error::new(
+                body.span(),
+                "interface methods are provided by the plugin and can't have a body",
+            ));
+        }
+        let sig = &mut method.sig;
+        if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+            return Err(syn::Error::new(
+                sig.span(),
+                "interface methods can't be generic or async",
+            ));
+        }
+        match sig.inputs.first() {
+            Some(FnArg::Receiver(receiver))
+                if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+            _ => {
+                return Err(syn::Error::new(
+                    sig.span(),
+                    "interface methods must take `&self`",
+                ))
+            }
+        }
+
+        let mut args = Vec::new();
+        let mut types = Vec::new();
+        for (i, input) in sig.inputs.iter_mut().skip(1).enumerate() {
+            let FnArg::Typed(arg) = input else {
+                unreachable!("only the first argument can be a receiver");
+            };
+            let ident = match &*arg.pat {
+                Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                    pat.ident.clone()
+                }
+                _ => {
+                    let ident = format_ident!("arg{i}");
+                    *arg.pat = syn::parse_quote!(#ident);
+                    ident
+                }
+            };
+            args.push(ident);
+            types.push((*arg.ty).clone());
+        }
+
+        let rets = match &sig.output {
+            ReturnType::Default => quote!(()),
+            ReturnType::Type(_, ty) => quote!(#ty),
+        };
+        sig.output = syn::parse_quote!(-> #krate::Result<#rets>);
+
+        let export = sig.ident.to_string();
+        let args_ty = quote!((#(#types,)*));
+        let index = checks.len();
+        checks.push(quote! {
+            plugin.checked_export::<#args_ty, #rets>(#export)?
+        });
+        let sig = &*sig;
+        methods.push(quote! {
+            #sig {
+                self.plugin
+                    .call_export::<#args_ty, #rets>(&self.exports[#index], (#(#args,)*))
+            }
+        });
+    }
+    let len = checks.len();
+
+    let doc = format!(" Calls [`{name}`] on a plugin, generated by `#[plugged::interface]`.");
+    Ok(quote! {
+        #item
+
+        #[doc = #doc]
+        #vis struct #proxy<'plugin, T = ()> {
+            plugin: &'plugin #krate::Plugin<T>,
+            exports: [#krate::CheckedExport; #len],
+        }
+
+        impl<'plugin, T: Send + 'static> #proxy<'plugin, T> {
+            /// Checks that `plugin` exports every method with a matching
+            /// signature, and keeps them to call.
+            #vis fn bind(plugin: &'plugin #krate::Plugin<T>) -> #krate::Result<Self> {
+                let exports = [#(#checks),*];
+                Ok(Self { plugin, exports })
+            }
+        }
+
+        impl<'plugin, T: Send + 'static> #name for #proxy<'plugin, T> {
+            #(#methods)*
+        }
+    })
+}
+
+fn host_crate() -> TokenStream {
+    match crate_name("plugged") {
+        Ok(FoundCrate::Itself) => quote!(crate),
+        Ok(FoundCrate::Name(name)) => {
+            let name = Ident::new(&name, Span::call_site());
+            quote!(::#name)
+        }
+        Err(_) => quote!(::plugged),
+    }
+}
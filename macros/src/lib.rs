@@ -0,0 +1,95 @@
+//! Procedural macros for [plugged](https://github.com/geekylthyosaur/plugged).
+//!
+//! Use them through the `plugged` and `plugged-guest` crates rather than
+//! depending on this one directly.
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod export;
+mod interface;
+mod manifest;
+
+/// Exports a Rust function from a plugin, generating the shim that moves its
+/// arguments and result across the boundary.
+///
+/// Numbers (`i32`, `u32`, `i64`, `u64`, `f32`, `f64`) are passed as wasm
+/// values and `String`, `&str`, `Vec<u8>` and `&[u8]` as buffers. Any other
+/// type, including `Result`, is passed as a buffer encoded with the JSON codec
+/// or the one given as `codec = path`. Panics are reported to the host.
+///
+/// The function's Rust signature is recorded in the `plugged.signatures`
+/// custom section, which the host reads with `Plugin::signatures`, and the
+/// plugin's manifest like [`manifest!`] records it.
+///
+/// ```ignore
+/// #[plugged::export]
+/// fn greet(name: &str) -> String {
+///     format!("Hello, {name}")
+/// }
+///
+/// #[plugged::export(codec = plugged::codec::MessagePack)]
+/// fn translate(point: Point, by: i32) -> Point {
+///     Point { x: point.x + by, y: point.y + by }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as export::Args);
+    let item = parse_macro_input!(item as syn::ItemFn);
+    export::expand(args, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generates a `{Trait}Proxy` calling a plugin's exports through the methods
+/// of the annotated trait.
+///
+/// Every method must take `&self` and wasm-compatible argument and result
+/// types, see `Plugin::function`. Methods return `plugged::Result` of their
+/// declared type. `bind` looks up every export up front and fails with
+/// `PluginError::Export` or `PluginError::TypeMismatch` if one is missing or
+/// has another signature.
+///
+/// ```ignore
+/// #[plugged::interface]
+/// trait Calculator {
+///     fn add(&self, a: i32, b: i32) -> i32;
+/// }
+///
+/// let plugin = Plugin::new("./examples/plugins/add.wat")?;
+/// let calculator = CalculatorProxy::bind(&plugin)?;
+/// assert_eq!(calculator.add(42, 1)?, 43);
+/// ```
+#[proc_macro_attribute]
+pub fn interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let span = proc_macro2::TokenStream::from(attr);
+        return syn::Error::new_spanned(span, "`interface` takes no arguments")
+            .into_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as syn::ItemTrait);
+    interface::expand(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Records the plugin's manifest in the `plugged.manifest` custom section,
+/// which the host reads with `Plugin::manifest`.
+///
+/// The name, version, authors and description come from the plugin's
+/// `Cargo.toml`. `#[export]` records them already, this is only needed to
+/// declare the host API version the plugin requires as `host_api`. Invoke it
+/// once per plugin.
+///
+/// ```ignore
+/// plugged::manifest!(host_api = "^1.0");
+/// ```
+#[proc_macro]
+pub fn manifest(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as manifest::Args);
+    manifest::expand(args)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
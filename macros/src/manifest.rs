@@ -0,0 +1,78 @@
+use std::env;
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    Ident, LitStr, Token,
+};
+
+const SECTION: &str = "plugged.manifest";
+
+pub struct Args {
+    host_api: Option<LitStr>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut host_api = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "host_api" => host_api = Some(input.parse()?),
+                _ => return Err(syn::Error::new(key.span(), "expected `host_api = \"...\"`")),
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(Self { host_api })
+    }
+}
+
+pub fn expand(args: Args) -> syn::Result<TokenStream> {
+    let host_api = args.host_api.map(|host_api| host_api.value());
+    record(host_api)
+        .ok_or_else(|| syn::Error::new(Span::call_site(), "`manifest!` must be built by Cargo"))
+}
+
+/// Records the manifest of the crate being compiled, or nothing if it isn't
+/// built by Cargo.
+///
+/// Every `#[export]` records one, so plugins get a manifest without asking.
+/// The host merges the copies.
+pub fn record(host_api: Option<String>) -> Option<TokenStream> {
+    // Cargo sets these for the crate being compiled, which is the plugin.
+    let package = |key: &str| env::var(format!("CARGO_PKG_{key}")).unwrap_or_default();
+    let name = package("NAME");
+    if name.is_empty() {
+        return None;
+    }
+    let authors = package("AUTHORS");
+    let authors: Vec<_> = authors
+        .split(':')
+        .filter(|author| !author.is_empty())
+        .collect();
+    let description = Some(package("DESCRIPTION")).filter(|description| !description.is_empty());
+    let host_api = host_api.filter(|host_api| !host_api.is_empty());
+
+    let manifest = serde_json::json!({
+        "name": name,
+        "version": package("VERSION"),
+        "authors": authors,
+        "description": description,
+        "host_api": host_api,
+    });
+    let manifest = format!("{manifest}\n");
+    let manifest_len = manifest.len();
+    let manifest = syn::LitByteStr::new(manifest.as_bytes(), Span::call_site());
+
+    Some(quote! {
+        const _: () = {
+            #[cfg_attr(target_arch = "wasm32", link_section = #SECTION)]
+            #[used]
+            static MANIFEST: [u8; #manifest_len] = *#manifest;
+        };
+    })
+}
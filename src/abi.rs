@@ -0,0 +1,404 @@
+//! Conversions between host values and the plugin calling convention.
+//!
+//! Numbers (`i32`, `u32`, `i64`, `u64`, `f32`, `f64`) are passed as the
+//! matching wasm value. Strings and byte buffers are copied through the
+//! guest's linear memory and passed as a single `i64` *fat pointer* holding
+//! `ptr << 32 | len`.
+//!
+//! Plugins receiving or returning buffers must export:
+//!
+//! - `alloc(len: i32) -> i32` returning a pointer to `len` writable bytes,
+//! - `dealloc(ptr: i32, len: i32)` releasing a buffer obtained from `alloc`.
+//!
+//! Argument buffers are allocated by the host before the call and released by
+//! the host once it returns, so the guest only borrows them. Result buffers
+//! are allocated by the guest and released by the host after copying them
+//! out. Empty buffers are never allocated: their fat pointer is `0` on the way
+//! in and any pointer with a zero length on the way out.
+//!
+//! The guest SDK declares the version of this convention it follows as a
+//! little endian `u32` in the `plugged.abi` custom section. Plugins declaring
+//! a version outside of [`VERSIONS`] are refused when loaded, plugins not
+//! declaring one are loaded as they are.
+
+use std::ops::RangeInclusive;
+
+use anyhow::anyhow;
+use wasmer::{Exports, Function, Module, StoreMut, Type, TypedFunction, Value};
+
+use crate::{limits, memory, PluginError, Result};
+
+pub(crate) const ALLOC: &str = "alloc";
+pub(crate) const DEALLOC: &str = "dealloc";
+
+/// Custom section the guest SDK declares its ABI version in.
+const SECTION: &str = "plugged.abi";
+
+/// ABI versions the host can call plugins with.
+pub(crate) const VERSIONS: RangeInclusive<u32> = 1..=1;
+
+/// Fails if `module` declares an ABI version the host doesn't support.
+pub(crate) fn check(module: &Module) -> Result<()> {
+    let mut sections = module.custom_sections(SECTION);
+    let Some(section) = sections.next() else {
+        return Ok(());
+    };
+    let version = <[u8; 4]>::try_from(&*section)
+        .ok()
+        .filter(|_| sections.next().is_none())
+        .ok_or_else(|| anyhow!("Invalid plugin ABI version"))?;
+    let version = u32::from_le_bytes(version);
+    if !VERSIONS.contains(&version) {
+        return Err(PluginError::IncompatibleAbi {
+            plugin: version,
+            host: VERSIONS,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn pack(ptr: u32, len: u32) -> i64 {
+    ((u64::from(ptr) << 32) | u64::from(len)) as i64
+}
+
+pub(crate) fn unpack(fat: i64) -> (u32, u32) {
+    ((fat as u64 >> 32) as u32, fat as u32)
+}
+
+/// A plugin instance in the middle of a call, used to move values across the
+/// boundary.
+pub struct Guest<'a> {
+    store: StoreMut<'a>,
+    exports: &'a Exports,
+    allocations: Vec<(i32, i32)>,
+}
+
+impl<'a> Guest<'a> {
+    pub(crate) fn new(store: StoreMut<'a>, exports: &'a Exports) -> Self {
+        Self {
+            store,
+            exports,
+            allocations: Vec::new(),
+        }
+    }
+
+    pub(crate) fn store(&mut self) -> &mut StoreMut<'a> {
+        &mut self.store
+    }
+
+    /// Copies `bytes` into a fresh guest allocation owned by this call.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) -> Result<i64> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        let len = i32::try_from(bytes.len()).map_err(|_| wasmer::MemoryAccessError::Overflow)?;
+        let alloc: TypedFunction<i32, i32> = self.exports.get_typed_function(&self.store, ALLOC)?;
+        let ptr = alloc.call(&mut self.store, len)?;
+        self.allocations.push((ptr, len));
+
+        let memory = self.exports.get_memory(memory::MEMORY)?;
+        memory.view(&self.store).write(ptr as u32 as u64, bytes)?;
+        Ok(pack(ptr as u32, len as u32))
+    }
+
+    /// Copies a guest-allocated buffer out and hands it back to the guest.
+    pub(crate) fn read_bytes(&mut self, fat: i64) -> Result<Vec<u8>> {
+        let (ptr, len) = unpack(fat);
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let memory = self.exports.get_memory(memory::MEMORY)?;
+        let bytes = memory::read_bytes(&memory.view(&self.store), ptr.into(), len as usize)?;
+        self.dealloc(ptr as i32, len as i32)?;
+        Ok(bytes)
+    }
+
+    /// Calls `f`, passing `args` in and taking its results out.
+    pub(crate) fn call<Args, Rets>(&mut self, f: &Function, args: Args) -> Result<Rets>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let args = args.into_values(self)?;
+        // Host functions fail calls by trapping with a `PluginError`.
+        let rets = f.call(&mut self.store, &args).map_err(|error| {
+            error
+                .downcast::<PluginError>()
+                .unwrap_or_else(PluginError::Runtime)
+        })?;
+        Rets::from_values(&rets, self)
+    }
+
+    /// Ends a call that returned `result`, reporting the guest's `panic` or
+    /// running out of fuel over the trap they caused.
+    pub(crate) fn finish<Rets>(
+        mut self,
+        result: Result<Rets>,
+        panic: Option<String>,
+    ) -> Result<Rets> {
+        if let Some(message) = panic {
+            // The panic tells more than failing to free the buffers would.
+            let _ = self.release();
+            return Err(PluginError::Panic(message));
+        }
+        if limits::exhausted(&mut self.store, self.exports) {
+            // Releasing the argument buffers needs fuel too, which the call
+            // used up, so it is lent and taken back.
+            limits::set_fuel(&mut self.store, self.exports, u64::MAX);
+            let released = self.release();
+            limits::set_fuel(&mut self.store, self.exports, 0);
+            released?;
+            return Err(PluginError::OutOfFuel);
+        }
+        self.release()?;
+        result
+    }
+
+    /// Releases every argument buffer allocated during this call.
+    fn release(&mut self) -> Result<()> {
+        for (ptr, len) in std::mem::take(&mut self.allocations) {
+            self.dealloc(ptr, len)?;
+        }
+        Ok(())
+    }
+
+    fn dealloc(&mut self, ptr: i32, len: i32) -> Result<()> {
+        let dealloc: TypedFunction<(i32, i32), ()> =
+            self.exports.get_typed_function(&self.store, DEALLOC)?;
+        Ok(dealloc.call(&mut self.store, ptr, len)?)
+    }
+}
+
+/// A single value that can be passed to a plugin function.
+pub trait IntoGuest {
+    #[doc(hidden)]
+    const TYPE: Type;
+
+    #[doc(hidden)]
+    fn into_guest(self, guest: &mut Guest) -> Result<Value>;
+}
+
+/// A single value that can be returned from a plugin function.
+pub trait FromGuest: Sized {
+    #[doc(hidden)]
+    const TYPE: Type;
+
+    #[doc(hidden)]
+    fn from_guest(value: &Value, guest: &mut Guest) -> Result<Self>;
+}
+
+/// Argument lists accepted by [`Plugin::function`](crate::Plugin::function):
+/// `()`, a single [`IntoGuest`] value or a tuple of them.
+pub trait Params {
+    #[doc(hidden)]
+    fn wasm_types() -> Vec<Type>;
+
+    #[doc(hidden)]
+    fn into_values(self, guest: &mut Guest) -> Result<Vec<Value>>;
+}
+
+/// Result lists returned by [`Plugin::function`](crate::Plugin::function):
+/// `()`, a single [`FromGuest`] value or a tuple of them.
+pub trait Results: Sized {
+    #[doc(hidden)]
+    fn wasm_types() -> Vec<Type>;
+
+    #[doc(hidden)]
+    fn from_values(values: &[Value], guest: &mut Guest) -> Result<Self>;
+}
+
+macro_rules! impl_number {
+    ($($ty:ty => $variant:ident as $wasm:ty),*) => {
+        $(
+            impl IntoGuest for $ty {
+                const TYPE: Type = Type::$variant;
+
+                fn into_guest(self, _: &mut Guest) -> Result<Value> {
+                    Ok(Value::$variant(self as $wasm))
+                }
+            }
+
+            impl FromGuest for $ty {
+                const TYPE: Type = Type::$variant;
+
+                fn from_guest(value: &Value, _: &mut Guest) -> Result<Self> {
+                    match *value {
+                        Value::$variant(v) => Ok(v as $ty),
+                        _ => unreachable!("signature is checked when the function is looked up"),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_number!(
+    i32 => I32 as i32,
+    u32 => I32 as i32,
+    i64 => I64 as i64,
+    u64 => I64 as i64,
+    f32 => F32 as f32,
+    f64 => F64 as f64
+);
+
+impl IntoGuest for &[u8] {
+    const TYPE: Type = Type::I64;
+
+    fn into_guest(self, guest: &mut Guest) -> Result<Value> {
+        guest.write_bytes(self).map(Value::I64)
+    }
+}
+
+impl IntoGuest for Vec<u8> {
+    const TYPE: Type = Type::I64;
+
+    fn into_guest(self, guest: &mut Guest) -> Result<Value> {
+        self.as_slice().into_guest(guest)
+    }
+}
+
+impl IntoGuest for &str {
+    const TYPE: Type = Type::I64;
+
+    fn into_guest(self, guest: &mut Guest) -> Result<Value> {
+        self.as_bytes().into_guest(guest)
+    }
+}
+
+impl IntoGuest for String {
+    const TYPE: Type = Type::I64;
+
+    fn into_guest(self, guest: &mut Guest) -> Result<Value> {
+        self.as_bytes().into_guest(guest)
+    }
+}
+
+impl FromGuest for Vec<u8> {
+    const TYPE: Type = Type::I64;
+
+    fn from_guest(value: &Value, guest: &mut Guest) -> Result<Self> {
+        let fat = i64::from_guest(value, guest)?;
+        guest.read_bytes(fat)
+    }
+}
+
+impl FromGuest for String {
+    const TYPE: Type = Type::I64;
+
+    fn from_guest(value: &Value, guest: &mut Guest) -> Result<Self> {
+        let bytes = Vec::<u8>::from_guest(value, guest)?;
+        String::from_utf8(bytes).map_err(|_| wasmer::MemoryAccessError::NonUtf8String.into())
+    }
+}
+
+impl<P: IntoGuest> Params for P {
+    fn wasm_types() -> Vec<Type> {
+        vec![P::TYPE]
+    }
+
+    fn into_values(self, guest: &mut Guest) -> Result<Vec<Value>> {
+        Ok(vec![self.into_guest(guest)?])
+    }
+}
+
+impl<R: FromGuest> Results for R {
+    fn wasm_types() -> Vec<Type> {
+        vec![R::TYPE]
+    }
+
+    fn from_values(values: &[Value], guest: &mut Guest) -> Result<Self> {
+        R::from_guest(&values[0], guest)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($x:ident),*) => {
+        impl<$($x: IntoGuest),*> Params for ($($x,)*) {
+            fn wasm_types() -> Vec<Type> {
+                vec![$($x::TYPE),*]
+            }
+
+            #[allow(non_snake_case, unused_variables)]
+            fn into_values(self, guest: &mut Guest) -> Result<Vec<Value>> {
+                let ($($x,)*) = self;
+                Ok(vec![$($x.into_guest(guest)?),*])
+            }
+        }
+
+        impl<$($x: FromGuest),*> Results for ($($x,)*) {
+            fn wasm_types() -> Vec<Type> {
+                vec![$($x::TYPE),*]
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn from_values(values: &[Value], guest: &mut Guest) -> Result<Self> {
+                let mut values = values.iter();
+                Ok(($($x::from_guest(values.next().unwrap(), guest)?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!();
+impl_tuple!(A1);
+impl_tuple!(A1, A2);
+impl_tuple!(A1, A2, A3);
+impl_tuple!(A1, A2, A3, A4);
+impl_tuple!(A1, A2, A3, A4, A5);
+impl_tuple!(A1, A2, A3, A4, A5, A6);
+impl_tuple!(A1, A2, A3, A4, A5, A6, A7);
+impl_tuple!(A1, A2, A3, A4, A5, A6, A7, A8);
+
+#[cfg(test)]
+mod tests {
+    use crate::{Plugin, PluginError, Result};
+
+    #[test]
+    fn abi_version() -> Result<()> {
+        let plugin = |version: &str| {
+            Plugin::from_bytes(format!(r#"(module (@custom "plugged.abi" "{version}"))"#))
+        };
+        plugin(r"\01\00\00\00")?;
+        let Err(PluginError::IncompatibleAbi { plugin: 2, host }) = plugin(r"\02\00\00\00") else {
+            panic!("expected an incompatible ABI");
+        };
+        assert_eq!(host, super::VERSIONS);
+        assert!(matches!(plugin(r"\01"), Err(PluginError::Load(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn strings() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let greet = plugin.function::<&str, String>("greet")?;
+        assert_eq!(greet("world")?, "Hello, world");
+
+        let freed = plugin.function::<(), i32>("freed")?;
+        assert_eq!(freed(())?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn bytes() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let greet = plugin.function::<Vec<u8>, Vec<u8>>("greet")?;
+        assert_eq!(greet(Vec::new())?, b"Hello, ");
+
+        let freed = plugin.function::<(), i32>("freed")?;
+        assert_eq!(freed(())?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_allocator_fail() -> Result<()> {
+        let plugin = Plugin::from_bytes(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "len") (param i64) (result i32) local.get 0 i32.wrap_i64))"#,
+        )?;
+        let len = plugin.function::<&str, i32>("len")?;
+        assert!(matches!(len("abc"), Err(PluginError::Export(_))));
+        assert_eq!(len("")?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Header of precompiled plugins.
+//!
+//! Compiled code is only valid for the wasmer version, CPU and configuration
+//! it was compiled with, so precompiled plugins start with:
+//!
+//! - [`MAGIC`]
+//! - [`VERSION`] of this layout, as a little endian `u32`
+//! - the length of the engine description as a little endian `u32`, followed
+//!   by the description
+//! - the hash of the compiled code
+//!
+//! followed by the code serialized by wasmer.
+
+use anyhow::anyhow;
+use wasmer_cache::Hash;
+
+use crate::{PluginError, Result};
+
+const MAGIC: &[u8; 8] = b"\0plugged";
+const VERSION: u32 = 1;
+const HASH_LEN: usize = 64;
+
+/// Prepends the header for `engine`, a description of everything `module`
+/// depends on, to the serialized `module`.
+pub(crate) fn encode(engine: &str, module: &[u8]) -> Vec<u8> {
+    let hash = Hash::generate(module).to_string();
+    let mut bytes = Vec::with_capacity(16 + engine.len() + HASH_LEN + module.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(engine.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(engine.as_bytes());
+    bytes.extend_from_slice(hash.as_bytes());
+    bytes.extend_from_slice(module);
+    bytes
+}
+
+/// Checks the header of `bytes` against `engine` and returns the serialized
+/// module after it.
+pub(crate) fn decode<'a>(engine: &str, bytes: &'a [u8]) -> Result<&'a [u8]> {
+    let rest = bytes
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| anyhow!("Not a precompiled plugin"))?;
+    let (version, rest) = split_u32(rest)?;
+    if version != VERSION {
+        return Err(PluginError::IncompatibleArtifact {
+            artifact: format!("artifact format {version}"),
+            host: format!("artifact format {VERSION}"),
+        });
+    }
+    let (len, rest) = split_u32(rest)?;
+    let (artifact, rest) = split(rest, len as usize)?;
+    let artifact = String::from_utf8_lossy(artifact);
+    if artifact != engine {
+        return Err(PluginError::IncompatibleArtifact {
+            artifact: artifact.into_owned(),
+            host: engine.to_owned(),
+        });
+    }
+    let (hash, module) = split(rest, HASH_LEN)?;
+    if hash != Hash::generate(module).to_string().as_bytes() {
+        return Err(anyhow!("Precompiled plugin is corrupted").into());
+    }
+    Ok(module)
+}
+
+fn split_u32(bytes: &[u8]) -> Result<(u32, &[u8])> {
+    let (int, rest) = split(bytes, 4)?;
+    Ok((u32::from_le_bytes(int.try_into().unwrap()), rest))
+}
+
+fn split(bytes: &[u8], len: usize) -> Result<(&[u8], &[u8])> {
+    if bytes.len() < len {
+        return Err(anyhow!("Precompiled plugin is truncated").into());
+    }
+    Ok(bytes.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Limits, Plugin, PluginError, Result};
+
+    fn artifact() -> Vec<u8> {
+        Plugin::precompile(std::fs::read("./examples/plugins/add.wat").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn precompiled() -> Result<()> {
+        let plugin = unsafe { Plugin::from_precompiled(artifact())? };
+        assert_eq!(plugin.function::<(i32, i32), i32>("add")?((42, 1))?, 43);
+        Ok(())
+    }
+
+    #[test]
+    fn incompatible_fail() {
+        let builder = Plugin::builder().limits(Limits::new().fuel(10));
+        let plugin = unsafe { builder.load_precompiled(artifact()) };
+        assert!(matches!(
+            plugin,
+            Err(PluginError::IncompatibleArtifact { .. })
+        ));
+
+        let mut artifact = artifact();
+        artifact[8] += 1;
+        let plugin = unsafe { Plugin::from_precompiled(artifact) };
+        assert!(matches!(
+            plugin,
+            Err(PluginError::IncompatibleArtifact { .. })
+        ));
+    }
+
+    #[test]
+    fn corrupted_fail() {
+        let mut corrupted = artifact();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let plugin = unsafe { Plugin::from_precompiled(corrupted) };
+        assert!(matches!(plugin, Err(PluginError::Load(_))));
+
+        let plugin = unsafe { Plugin::from_precompiled(&artifact()[..20]) };
+        assert!(matches!(plugin, Err(PluginError::Load(_))));
+
+        let plugin = unsafe { Plugin::from_precompiled(b"(module)") };
+        assert!(matches!(plugin, Err(PluginError::Load(_))));
+    }
+}
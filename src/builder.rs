@@ -0,0 +1,420 @@
+//! Configuration collected before a plugin is loaded.
+
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use wasmer::{
+    CompilerConfig, Cranelift, Engine, Features, FunctionEnv, Instance, Module, NativeEngineExt,
+    Store,
+};
+
+use crate::{
+    abi, artifact, cache::Cache, deadline::Deadline, imports::HostEnv, manifest, memory,
+    tunables::MemoryUsage, Imports, Limits, Plugin, PluginPool, PoolConfig, Result, WatchedPlugin,
+};
+
+/// Compiler turning a plugin's wasm into native code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compiler {
+    /// Optimizing compiler, the default.
+    #[default]
+    Cranelift,
+    /// Single pass compiler, fast to compile but producing slower code.
+    #[cfg(feature = "singlepass")]
+    Singlepass,
+}
+
+impl Compiler {
+    fn config(self) -> Box<dyn CompilerConfig> {
+        match self {
+            Self::Cranelift => Box::new(Cranelift::default()),
+            #[cfg(feature = "singlepass")]
+            Self::Singlepass => Box::new(wasmer::Singlepass::default()),
+        }
+    }
+}
+
+/// Loads a plugin with host state of type `T` once configured.
+///
+/// ```
+/// use plugged::{Imports, Limits, PluginBuilder};
+///
+/// let plugin = PluginBuilder::with_state(0)
+///     .imports(Imports::new().function("env", "double", |x: i32| x * 2))
+///     .limits(Limits::new().fuel_per_call(10_000))
+///     .load("./examples/plugins/host.wat")?;
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+#[derive(Clone)]
+pub struct PluginBuilder<T = ()> {
+    pub(crate) data: T,
+    imports: Imports<T>,
+    limits: Limits,
+    compiler: Compiler,
+    features: Option<Features>,
+    #[cfg(feature = "wasi")]
+    wasi: Option<crate::Wasi>,
+    cache_dir: Option<PathBuf>,
+    cache_size: Option<u64>,
+}
+
+impl PluginBuilder {
+    pub fn new() -> Self {
+        Self::with_state(())
+    }
+}
+
+impl Default for PluginBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + 'static> PluginBuilder<T> {
+    /// Starts configuring a plugin owning the host state `data`, which host
+    /// functions reach through their [`Context`](crate::Context).
+    pub fn with_state(data: T) -> Self {
+        Self {
+            data,
+            imports: Imports::new(),
+            limits: Limits::default(),
+            compiler: Compiler::default(),
+            features: None,
+            #[cfg(feature = "wasi")]
+            wasi: None,
+            cache_dir: None,
+            cache_size: None,
+        }
+    }
+
+    pub fn imports(mut self, imports: Imports<T>) -> Self {
+        self.imports = imports;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn compiler(mut self, compiler: Compiler) -> Self {
+        self.compiler = compiler;
+        self
+    }
+
+    /// Sets the wasm proposals the plugin may use, instead of the ones the
+    /// compiler supports by default.
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Links the WASI imports configured by `wasi`, see
+    /// [`Imports::wasi`](crate::Imports::wasi).
+    #[cfg(feature = "wasi")]
+    pub fn wasi(mut self, wasi: crate::Wasi) -> Self {
+        self.wasi = Some(wasi);
+        self
+    }
+
+    /// Keeps compiled plugins in `dir`, so loading the same plugin with the
+    /// same configuration and wasmer version on the same CPU again skips
+    /// compilation.
+    ///
+    /// # Safety
+    ///
+    /// Only this crate may write to `dir`. Cached plugins are loaded like
+    /// [`load_precompiled`](Self::load_precompiled) loads them, so a crafted
+    /// file in `dir` runs its native code unchecked.
+    pub unsafe fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Caps the compiled plugins in the [cache directory](Self::cache_dir) at
+    /// `bytes`, removing the least recently loaded ones once they grow past
+    /// it. Other files in the directory are left alone.
+    pub fn cache_size(mut self, bytes: u64) -> Self {
+        self.cache_size = Some(bytes);
+        self
+    }
+
+    /// Loads the plugin from a `.wasm` or `.wat` file.
+    pub fn load(self, path: impl AsRef<Path>) -> Result<Plugin<T>> {
+        let bytes = std::fs::read(path.as_ref()).map_err(anyhow::Error::from)?;
+        self.load_bytes(bytes)
+    }
+
+    /// Loads the plugin from wasm or wat source.
+    pub fn load_bytes(self, bytes: impl AsRef<[u8]>) -> Result<Plugin<T>> {
+        let (engine, memory_usage) = self
+            .limits
+            .engine(self.compiler.config(), self.features.clone());
+        let module = self.compile(&engine, bytes.as_ref())?;
+        self.instantiate(Store::new(engine), module, memory_usage)
+    }
+
+    /// Compiles the plugin from a `.wasm` or `.wat` file once, and keeps
+    /// instances of it in a pool, see [`PluginPool`].
+    pub fn pool(self, path: impl AsRef<Path>, config: PoolConfig) -> Result<PluginPool<T>>
+    where
+        T: Clone,
+    {
+        let bytes = std::fs::read(path.as_ref()).map_err(anyhow::Error::from)?;
+        self.pool_bytes(bytes, config)
+    }
+
+    /// Like [`pool`](Self::pool), from wasm or wat source.
+    pub fn pool_bytes(self, bytes: impl AsRef<[u8]>, config: PoolConfig) -> Result<PluginPool<T>>
+    where
+        T: Clone,
+    {
+        let (engine, memory_usage) = self
+            .limits
+            .engine(self.compiler.config(), self.features.clone());
+        let module = self.compile(&engine, bytes.as_ref())?;
+        PluginPool::new(self, engine, module, memory_usage, config)
+    }
+
+    /// Loads the plugin from a `.wasm` or `.wat` file, and reloads it in the
+    /// background when the file changes, checking every `interval`. See
+    /// [`WatchedPlugin`].
+    pub fn watch(self, path: impl Into<PathBuf>, interval: Duration) -> Result<WatchedPlugin<T>>
+    where
+        T: Clone,
+    {
+        WatchedPlugin::new(self, path.into(), interval)
+    }
+
+    /// Loads a plugin compiled by [`precompile`](Self::precompile).
+    ///
+    /// Fails with [`PluginError::IncompatibleArtifact`](crate::PluginError::IncompatibleArtifact)
+    /// if it was compiled by another wasmer version, for another CPU or with
+    /// another compiler, features or limits.
+    ///
+    /// # Safety
+    ///
+    /// `bytes` must come from `precompile`. Their header catches mismatched
+    /// and corrupted artifacts, but the native code of a crafted one would
+    /// run unchecked.
+    pub unsafe fn load_precompiled(self, bytes: impl AsRef<[u8]>) -> Result<Plugin<T>> {
+        let (engine, memory_usage) = self
+            .limits
+            .engine(self.compiler.config(), self.features.clone());
+        let module = artifact::decode(&self.describe(&engine), bytes.as_ref())?;
+        let module = Module::deserialize(&engine, module).map_err(anyhow::Error::from)?;
+        self.instantiate(Store::new(engine), module, memory_usage)
+    }
+
+    /// Compiles wasm or wat source to native code loadable with
+    /// [`load_precompiled`](Self::load_precompiled).
+    pub fn precompile(&self, bytes: impl AsRef<[u8]>) -> Result<Vec<u8>> {
+        let (engine, _) = self
+            .limits
+            .engine(self.compiler.config(), self.features.clone());
+        let module = Module::new(&engine, bytes).map_err(anyhow::Error::from)?;
+        let module = module.serialize().map_err(anyhow::Error::from)?;
+        Ok(artifact::encode(&self.describe(&engine), &module))
+    }
+
+    /// Describes everything code compiled by `engine` depends on besides the
+    /// wasm.
+    fn describe(&self, engine: &Engine) -> String {
+        let target = engine.target();
+        format!(
+            "wasmer {} {} {:?}, {:?} {:?} {:?} {:?} {:?}",
+            wasmer::VERSION,
+            target.triple(),
+            target.cpu_features(),
+            self.compiler,
+            self.features,
+            self.limits.metering(),
+            self.limits.interruptible(),
+            self.limits.memory
+        )
+    }
+
+    /// Compiles `bytes`, going through the cache directory if one is set.
+    fn compile(&self, engine: &Engine, bytes: &[u8]) -> Result<Module> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(Module::new(engine, bytes).map_err(anyhow::Error::from)?);
+        };
+        let description = self.describe(engine);
+        let cache = Cache::new(dir, self.cache_size, &description);
+        let key = cache.key(bytes);
+        if let Some(module) = cache.load(engine, &key) {
+            return Ok(module);
+        }
+        let module = Module::new(engine, bytes).map_err(anyhow::Error::from)?;
+        cache.store(&key, &module);
+        Ok(module)
+    }
+
+    pub(crate) fn instantiate(
+        self,
+        mut store: Store,
+        module: Module,
+        memory_usage: Arc<MemoryUsage>,
+    ) -> Result<Plugin<T>> {
+        abi::check(&module)?;
+        let manifest = manifest::parse(&module)?;
+        #[allow(unused_mut)]
+        let mut imports = self.imports;
+        #[cfg(feature = "wasi")]
+        if let Some(wasi) = self.wasi {
+            imports = imports.wasi(wasi);
+        }
+        let env = FunctionEnv::new(&mut store, HostEnv::new(self.data));
+        let mut import_objects = imports.build(&mut store, &env);
+        let deadline = Deadline::define(&mut store, &module, &mut import_objects);
+        #[cfg(feature = "wasi")]
+        let mut wasi = match &imports.wasi {
+            Some(wasi) => Some(wasi.build(&mut store, &module, &mut import_objects)?),
+            None => None,
+        };
+        let instance =
+            Instance::new(&mut store, &module, &import_objects).map_err(anyhow::Error::from)?;
+        #[cfg(feature = "wasi")]
+        if let Some(wasi) = &mut wasi {
+            wasi.initialize(&mut store, &instance)?;
+        }
+        let host = env.as_mut(&mut store);
+        host.memory = instance.exports.get_memory(memory::MEMORY).ok().cloned();
+        host.exports = instance.exports.clone();
+
+        Ok(Plugin {
+            module,
+            manifest,
+            instance,
+            env,
+            limits: self.limits,
+            deadline,
+            poisoned: Cell::new(false),
+            trapped: Cell::new(false),
+            memory_usage,
+            #[cfg(feature = "wasi")]
+            wasi,
+            store: RefCell::new(store),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::Features;
+
+    use crate::{Compiler, Context, Imports, Limits, Plugin, PluginBuilder, PluginError, Result};
+
+    fn double() -> Imports<u32> {
+        Imports::new().function("env", "double", |mut ctx: Context<u32>, x: i32| {
+            *ctx.data_mut() += 1;
+            x * 2
+        })
+    }
+
+    #[test]
+    fn builder() -> Result<()> {
+        let plugin = PluginBuilder::with_state(0)
+            .imports(double())
+            .limits(Limits::new().fuel(1_000))
+            .compiler(Compiler::Cranelift)
+            .load("./examples/plugins/host.wat")?;
+        assert_eq!(plugin.function::<i32, i32>("quadruple")?(3)?, 12);
+        assert_eq!(*plugin.data()?, 2);
+        assert!(plugin.fuel()?.unwrap() < 1_000);
+        Ok(())
+    }
+
+    #[test]
+    fn precompiled() -> Result<()> {
+        let builder = Plugin::builder().limits(Limits::new().memory_pages(4));
+        let artifact = builder.precompile(std::fs::read("./examples/plugins/grow.wat").unwrap())?;
+        let plugin = unsafe { builder.load_precompiled(artifact)? };
+        assert_eq!(plugin.function::<i32, i32>("grow")?(4)?, -1);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_dir() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("plugged-cache-{}", std::process::id()));
+        for _ in 0..2 {
+            let builder = PluginBuilder::with_state(0).imports(double());
+            let plugin = unsafe { builder.cache_dir(&dir) }.load("./examples/plugins/host.wat")?;
+            assert_eq!(plugin.function::<i32, i32>("quadruple")?(3)?, 12);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let builder = PluginBuilder::with_state(0)
+            .imports(double())
+            .limits(Limits::new().fuel(10));
+        let plugin = unsafe { builder.cache_dir(&dir) }.load("./examples/plugins/host.wat")?;
+        assert!(plugin.fuel()?.is_some());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn cache_size() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("plugged-cache-size-{}", std::process::id()));
+        let builder = unsafe { PluginBuilder::new().cache_dir(&dir) };
+        for plugin in ["add", "loop", "add"] {
+            builder
+                .clone()
+                .load(format!("./examples/plugins/{plugin}.wat"))?;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let size = |plugin| {
+            let wat = std::fs::read(format!("./examples/plugins/{plugin}.wat")).unwrap();
+            builder.precompile(wat).unwrap().len() as u64
+        };
+        let max_size = size("add") + size("grow");
+        let other = dir.join("other");
+        let partial = dir.join(format!("{}.1.0.partial", "0".repeat(64)));
+        std::fs::write(&other, [0; 16]).unwrap();
+        std::fs::write(&partial, [0; 16]).unwrap();
+        builder
+            .clone()
+            .cache_size(max_size)
+            .load("./examples/plugins/grow.wat")?;
+        assert!(other.exists() && partial.exists());
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|file| file.unwrap())
+            .filter(|file| ![&other, &partial].contains(&&file.path()))
+            .collect();
+        let total: u64 = files
+            .iter()
+            .map(|file| file.metadata().unwrap().len())
+            .sum();
+        assert_eq!((files.len(), total), (2, max_size));
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn features_fail() {
+        let wat =
+            r#"(module (memory 1) (func (memory.fill (i32.const 0) (i32.const 0) (i32.const 0))))"#;
+        assert!(Plugin::builder().load_bytes(wat).is_ok());
+        let mut features = Features::new();
+        features.bulk_memory(false);
+        let plugin = Plugin::builder().features(features).load_bytes(wat);
+        assert!(matches!(plugin, Err(PluginError::Load(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "singlepass")]
+    fn singlepass() -> Result<()> {
+        let plugin = Plugin::builder()
+            .compiler(Compiler::Singlepass)
+            .load("./examples/plugins/add.wat")?;
+        assert_eq!(plugin.function::<(i32, i32), i32>("add")?((1, 2))?, 3);
+        Ok(())
+    }
+}
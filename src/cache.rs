@@ -0,0 +1,136 @@
+//! On-disk cache of compiled plugins.
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use wasmer::{Engine, Module};
+use wasmer_cache::Hash;
+
+use crate::artifact;
+
+/// Compiled modules stored in a directory, one file per key, in the format
+/// of precompiled plugins.
+///
+/// Files are touched on every hit, so eviction drops the least recently used
+/// ones first. Failing to read or write the cache only costs a recompilation,
+/// so errors are ignored.
+pub(crate) struct Cache<'a> {
+    dir: &'a Path,
+    max_size: Option<u64>,
+    /// Description of the engine compiling the modules, see
+    /// [`artifact`](crate::artifact).
+    engine: &'a str,
+}
+
+impl<'a> Cache<'a> {
+    pub(crate) fn new(dir: &'a Path, max_size: Option<u64>, engine: &'a str) -> Self {
+        Self {
+            dir,
+            max_size,
+            engine,
+        }
+    }
+
+    /// Key of the module compiled from `bytes`.
+    pub(crate) fn key(&self, bytes: &[u8]) -> String {
+        Hash::generate(&[bytes, self.engine.as_bytes()].concat()).to_string()
+    }
+
+    pub(crate) fn load(&self, engine: &Engine, key: &str) -> Option<Module> {
+        let path = self.path(key);
+        let bytes = fs::read(&path).ok()?;
+        let module = artifact::decode(self.engine, &bytes)
+            .ok()
+            .and_then(|module| {
+                // SAFETY: the caller of `PluginBuilder::cache_dir` vouches
+                // that only `store` writes to the directory.
+                unsafe { Module::deserialize(engine, module).ok() }
+            });
+        match module {
+            Some(module) => {
+                let _ = File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                Some(module)
+            }
+            // Corrupted or stored by an incompatible version, replaced once
+            // recompiled.
+            None => {
+                let _ = fs::remove_file(path);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn store(&self, key: &str, module: &Module) {
+        let Ok(module) = module.serialize() else {
+            return;
+        };
+        let artifact = artifact::encode(self.engine, &module);
+        // Written aside and renamed, so other processes never load half a
+        // module. Every write gets its own file, even from the same process.
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let write = WRITES.fetch_add(1, Ordering::Relaxed);
+        let partial = self
+            .dir
+            .join(format!("{key}.{}.{write}.partial", std::process::id()));
+        let stored = fs::create_dir_all(self.dir)
+            .and_then(|_| fs::write(&partial, &artifact))
+            .and_then(|_| fs::rename(&partial, self.path(key)));
+        if stored.is_err() {
+            let _ = fs::remove_file(partial);
+            return;
+        }
+        if let Some(max_size) = self.max_size {
+            self.evict(max_size);
+        }
+    }
+
+    /// Removes the least recently used modules until the cache fits in
+    /// `max_size` bytes.
+    ///
+    /// Only modules count, other files in the directory and modules still
+    /// being written are left alone.
+    fn evict(&self, max_size: u64) {
+        let Ok(entries) = fs::read_dir(self.dir) else {
+            return;
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                if !entry.file_name().to_str().is_some_and(is_key) {
+                    return None;
+                }
+                let metadata = entry.metadata().ok()?;
+                let modified = metadata.modified().ok()?;
+                metadata
+                    .is_file()
+                    .then(|| (modified, metadata.len(), entry.path()))
+            })
+            .collect();
+        files.sort();
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in files {
+            if size <= max_size {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                size -= len;
+            }
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+}
+
+/// Tells whether `name` is a key, which modules are stored under.
+fn is_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
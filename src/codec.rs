@@ -0,0 +1,61 @@
+//! Encodings used by [`Plugin::call_serde`](crate::Plugin::call_serde) to
+//! move structured values through guest memory.
+
+pub use plugged_codec::*;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{Plugin, PluginError, Result};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn json() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let point = Point { x: 4, y: 2 };
+        let echoed: Point = plugin.call_serde("echo", &point)?;
+        assert_eq!(echoed, point);
+        Ok(())
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let point = Point { x: 4, y: 2 };
+        let echoed: Point = plugin.call_serde_with(&super::MessagePack, "echo", &point)?;
+        assert_eq!(echoed, point);
+        Ok(())
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let point = Point { x: 4, y: 2 };
+        let echoed: Point = plugin.call_serde_with(&super::Bincode, "echo", &point)?;
+        assert_eq!(echoed, point);
+        Ok(())
+    }
+
+    #[test]
+    fn codec_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+
+        let input = HashMap::from([((1, 2), 3)]);
+        let result = plugin.call_serde::<_, ()>("echo", &input);
+        assert!(matches!(result, Err(PluginError::Encode(_))));
+
+        let result = plugin.call_serde::<_, Vec<String>>("echo", &Point { x: 4, y: 2 });
+        assert!(matches!(result, Err(PluginError::Decode(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+//! Interrupts plugin calls that run past their deadline or are cancelled.
+//!
+//! Plugins loaded with a timeout or fuel are compiled with [`Checkpoints`],
+//! which makes the guest import [`CHECK`] and call it every [`INTERVAL`]
+//! function entries and loop iterations. The host function runs on the thread
+//! of the call, between two guest instructions, so it reads the deadline and
+//! traps without racing the guest.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use wasmer::{
+    wasmparser::{BlockType, Operator},
+    ExportIndex, Function, FunctionEnv, FunctionEnvMut, FunctionMiddleware, FunctionType,
+    GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState, Module,
+    ModuleMiddleware, Mutability, RuntimeError, Store, Type,
+};
+use wasmer_types::{
+    entity::EntityRef, FunctionIndex, GlobalIndex, ImportIndex, ImportKey, ModuleInfo,
+};
+
+use crate::imports::BUILTIN;
+
+/// Host function the guest calls to check its deadline.
+const CHECK: &str = "deadline";
+
+/// Function entries and loop iterations between two checks.
+const INTERVAL: i32 = 10_000;
+
+/// Middleware making the guest check its deadline, see the module docs.
+///
+/// The check is imported as function 0, so every other function index of
+/// the module is shifted by one.
+#[derive(Debug, Default)]
+pub(crate) struct Checkpoints {
+    /// Global counting down to the next check, in the module being compiled.
+    ticks: Mutex<Option<GlobalIndex>>,
+}
+
+impl ModuleMiddleware for Checkpoints {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionCheckpoints {
+            ticks: self
+                .ticks
+                .lock()
+                .unwrap()
+                .expect("module info not transformed"),
+            started: false,
+        })
+    }
+
+    fn transform_module_info(&self, module: &mut ModuleInfo) {
+        let shift = |index: FunctionIndex| FunctionIndex::new(index.index() + 1);
+
+        let signature = module.signatures.push(FunctionType::new([], [Type::I32]));
+        let functions = std::mem::take(&mut module.functions);
+        module.functions = std::iter::once(signature)
+            .chain(functions.values().copied())
+            .collect();
+        module.num_imported_functions += 1;
+
+        // Imports are resolved in order, and functions first in index order.
+        let check = ImportKey {
+            module: BUILTIN.to_owned(),
+            field: CHECK.to_owned(),
+            import_idx: 0,
+        };
+        let imports = std::mem::take(&mut module.imports);
+        let imports = imports.into_iter().map(|(key, index)| {
+            let key = ImportKey {
+                import_idx: key.import_idx + 1,
+                ..key
+            };
+            let index = match index {
+                ImportIndex::Function(function) => ImportIndex::Function(shift(function)),
+                index => index,
+            };
+            (key, index)
+        });
+        let check = (check, ImportIndex::Function(FunctionIndex::new(0)));
+        module.imports = std::iter::once(check).chain(imports).collect();
+
+        for export in module.exports.values_mut() {
+            if let ExportIndex::Function(function) = export {
+                *function = shift(*function);
+            }
+        }
+        module.start_function = module.start_function.map(shift);
+        for initializer in &mut module.table_initializers {
+            initializer.elements.iter_mut().for_each(|f| *f = shift(*f));
+        }
+        for elements in module.passive_elements.values_mut() {
+            elements.iter_mut().for_each(|f| *f = shift(*f));
+        }
+        for initializer in module.global_initializers.values_mut() {
+            if let GlobalInit::RefFunc(function) = initializer {
+                *function = shift(*function);
+            }
+        }
+        module.function_names = std::mem::take(&mut module.function_names)
+            .into_iter()
+            .map(|(function, name)| (shift(function), name))
+            .collect();
+
+        let ticks = module
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module
+            .global_initializers
+            .push(GlobalInit::I32Const(INTERVAL));
+        *self.ticks.lock().unwrap() = Some(ticks);
+    }
+}
+
+#[derive(Debug)]
+struct FunctionCheckpoints {
+    ticks: GlobalIndex,
+    /// Whether the check on entry was emitted.
+    started: bool,
+}
+
+impl FunctionCheckpoints {
+    /// Counts down, calling the check when the count is over.
+    fn tick(&self, state: &mut MiddlewareReaderState) {
+        let ticks = self.ticks.as_u32();
+        state.extend([
+            Operator::GlobalGet {
+                global_index: ticks,
+            },
+            Operator::I32Eqz,
+            Operator::If {
+                blockty: BlockType::Empty,
+            },
+            Operator::Call { function_index: 0 },
+            Operator::GlobalSet {
+                global_index: ticks,
+            },
+            Operator::End,
+            Operator::GlobalGet {
+                global_index: ticks,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::I32Sub,
+            Operator::GlobalSet {
+                global_index: ticks,
+            },
+        ]);
+    }
+}
+
+impl FunctionMiddleware for FunctionCheckpoints {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if !self.started {
+            self.started = true;
+            self.tick(state);
+        }
+        match operator {
+            Operator::Call { function_index } => state.push_operator(Operator::Call {
+                function_index: function_index + 1,
+            }),
+            Operator::ReturnCall { function_index } => state.push_operator(Operator::ReturnCall {
+                function_index: function_index + 1,
+            }),
+            Operator::RefFunc { function_index } => state.push_operator(Operator::RefFunc {
+                function_index: function_index + 1,
+            }),
+            Operator::Loop { .. } => {
+                state.push_operator(operator);
+                self.tick(state);
+            }
+            operator => state.push_operator(operator),
+        }
+        Ok(())
+    }
+}
+
+/// Lets another thread cancel a call, e.g. when the future awaiting it is
+/// dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Cancel(AtomicBool);
+
+impl Cancel {
+    #[cfg(feature = "async")]
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Deadline of the call running on a plugin compiled with [`Checkpoints`].
+#[derive(Debug, Default)]
+pub(crate) struct Deadline {
+    at: Option<Instant>,
+    cancel: Option<Arc<Cancel>>,
+    /// Whether the check stopped the call.
+    expired: bool,
+}
+
+impl Deadline {
+    /// Defines the check imported by `module`, if it was compiled with
+    /// [`Checkpoints`].
+    pub(crate) fn define(
+        store: &mut Store,
+        module: &Module,
+        imports: &mut wasmer::Imports,
+    ) -> Option<FunctionEnv<Self>> {
+        let checked = module
+            .imports()
+            .any(|import| import.module() == BUILTIN && import.name() == CHECK);
+        if !checked {
+            return None;
+        }
+        let env = FunctionEnv::new(store, Self::default());
+        let check = Function::new_typed_with_env(store, &env, check);
+        imports.define(BUILTIN, CHECK, check);
+        Some(env)
+    }
+
+    /// Arms the check for a call, which is stopped at `at` or once `cancel`
+    /// is cancelled.
+    pub(crate) fn start(&mut self, at: Option<Instant>, cancel: Option<&Arc<Cancel>>) {
+        self.at = at;
+        self.cancel = cancel.cloned();
+        self.expired = false;
+    }
+
+    /// Disarms the check and tells whether it stopped the call.
+    pub(crate) fn finish(&mut self) -> bool {
+        self.at = None;
+        self.cancel = None;
+        std::mem::take(&mut self.expired)
+    }
+}
+
+fn check(mut env: FunctionEnvMut<Deadline>) -> Result<i32, RuntimeError> {
+    let deadline = env.data_mut();
+    let cancelled = deadline.cancel.as_deref().is_some_and(Cancel::is_cancelled);
+    if cancelled || deadline.at.is_some_and(|at| at <= Instant::now()) {
+        deadline.expired = true;
+        return Err(RuntimeError::new("plugin call interrupted"));
+    }
+    Ok(INTERVAL)
+}
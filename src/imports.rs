@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use crate::{
+    abi::{self, Guest},
+    memory, Params, Results,
+};
+use wasmer::{
+    AsStoreMut, AsStoreRef, Exports, FromToNativeWasmType, FunctionEnv, FunctionEnvMut, Memory,
+    MemoryAccessError, MemoryView, Store, StoreRef, TypedFunction, WasmTypeList,
+};
+
+/// Namespace of the imports every plugin may link against.
+pub(crate) const BUILTIN: &str = "plugged";
+
+type Factory<T> =
+    Arc<dyn Fn(&mut Store, &FunctionEnv<HostEnv<T>>) -> wasmer::Function + Send + Sync>;
+
+/// Host functions linked into a plugin when it is instantiated.
+///
+/// ```
+/// use plugged::{Imports, Plugin};
+///
+/// let imports = Imports::new().function("env", "double", |x: i32| x * 2);
+/// let plugin = Plugin::with_imports("./examples/plugins/host.wat", imports)?;
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+pub struct Imports<T = ()> {
+    functions: Vec<(String, String, Factory<T>)>,
+    #[cfg(feature = "wasi")]
+    pub(crate) wasi: Option<crate::Wasi>,
+}
+
+impl<T> Imports<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `f` as the import `namespace`.`name`.
+    ///
+    /// `f` is either a plain closure over wasm values or a closure taking a
+    /// [`Context`] as its first argument. Registering the same name twice
+    /// replaces the previous function.
+    pub fn function<F, Args, Rets, Kind>(
+        mut self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+        f: F,
+    ) -> Self
+    where
+        F: IntoHostFunction<T, Args, Rets, Kind>,
+    {
+        let (namespace, name) = (namespace.into(), name.into());
+        let f = Arc::new(f);
+        let factory: Factory<T> = Arc::new(move |store, env| f.clone().into_function(store, env));
+
+        self.functions
+            .retain(|(ns, n, _)| !(*ns == namespace && *n == name));
+        self.functions.push((namespace, name, factory));
+        self
+    }
+
+    /// Registers the async closure `f` as the import `namespace`.`name`,
+    /// which the guest calls like any other import.
+    ///
+    /// Like with [`function`](Self::function), `f` may take a [`Context`]
+    /// first. The future it returns can't borrow the context, so `f` reads
+    /// what the future needs before returning it.
+    ///
+    /// The guest can't be suspended, so it waits for the future with its
+    /// thread blocked: every pending call to `f` holds up a thread until the
+    /// future completes. Calling the plugin with
+    /// [`SharedFunction::call_async`](crate::SharedFunction::call_async)
+    /// runs the future on the caller's tokio runtime while the guest's thread
+    /// from the blocking pool waits for it. Other calls block their own
+    /// thread on the future, which fails with
+    /// [`PluginError::CurrentThreadRuntime`](crate::PluginError::CurrentThreadRuntime)
+    /// on a current-thread runtime.
+    #[cfg(feature = "async")]
+    pub fn function_async<F, Args, Rets, Kind>(
+        self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+        f: F,
+    ) -> Self
+    where
+        F: IntoHostFunction<T, Args, Rets, Async<Kind>>,
+    {
+        self.function(namespace, name, f)
+    }
+
+    /// Links the WASI imports configured by `wasi`, for plugins compiled to
+    /// `wasm32-wasi`.
+    #[cfg(feature = "wasi")]
+    pub fn wasi(mut self, wasi: crate::Wasi) -> Self {
+        self.wasi = Some(wasi);
+        self
+    }
+
+    pub(crate) fn build(&self, store: &mut Store, env: &FunctionEnv<HostEnv<T>>) -> wasmer::Imports
+    where
+        T: Send + 'static,
+    {
+        let mut imports = wasmer::Imports::new();
+        imports.define(
+            BUILTIN,
+            "panic",
+            wasmer::Function::new_typed_with_env(store, env, panic),
+        );
+        for (namespace, name, factory) in &self.functions {
+            imports.define(namespace, name, factory(store, env));
+        }
+        imports
+    }
+}
+
+impl<T> Default for Imports<T> {
+    fn default() -> Self {
+        Self {
+            functions: Vec::new(),
+            #[cfg(feature = "wasi")]
+            wasi: None,
+        }
+    }
+}
+
+impl<T> Clone for Imports<T> {
+    fn clone(&self) -> Self {
+        Self {
+            functions: self.functions.clone(),
+            #[cfg(feature = "wasi")]
+            wasi: self.wasi.clone(),
+        }
+    }
+}
+
+/// Records the message a guest reports through `plugged.panic` right before
+/// it traps.
+fn panic<T: Send + 'static>(mut env: FunctionEnvMut<HostEnv<T>>, message: i64) {
+    let (ptr, len) = abi::unpack(message);
+    let (host, store) = env.data_and_store_mut();
+    let message = host
+        .memory
+        .as_ref()
+        .and_then(|memory| memory::read_bytes(&memory.view(&store), ptr.into(), len as usize).ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_else(|| "plugin panicked".into());
+    host.panic = Some(message);
+}
+
+/// Host-side state of a single plugin instance, owned by its `Store`.
+pub struct HostEnv<T> {
+    pub(crate) data: T,
+    pub(crate) memory: Option<Memory>,
+    pub(crate) exports: Exports,
+    pub(crate) panic: Option<String>,
+}
+
+impl<T> HostEnv<T> {
+    pub(crate) fn new(data: T) -> Self {
+        Self {
+            data,
+            memory: None,
+            exports: Exports::new(),
+            panic: None,
+        }
+    }
+}
+
+/// Handle passed to host functions registered with a leading `Context`
+/// argument.
+///
+/// Gives access to the plugin's host state and its exported `memory`.
+pub struct Context<'a, T: 'a> {
+    env: FunctionEnvMut<'a, HostEnv<T>>,
+}
+
+impl<'a, T: Send + 'static> Context<'a, T> {
+    fn new(env: FunctionEnvMut<'a, HostEnv<T>>) -> Self {
+        Self { env }
+    }
+
+    pub fn data(&self) -> &T {
+        &self.env.data().data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.env.data_mut().data
+    }
+
+    /// Returns a view of the plugin's `memory` export, if it has one.
+    pub fn memory(&self) -> Option<MemoryView<'_>> {
+        let memory = self.env.data().memory.as_ref()?;
+        Some(memory.view(&self.env))
+    }
+
+    memory::memory_accessors!();
+
+    /// Copies out a buffer the guest lent to this call as a fat pointer.
+    pub fn read_buffer(&self, fat: i64) -> crate::Result<Vec<u8>> {
+        let (ptr, len) = abi::unpack(fat);
+        self.read_bytes(ptr.into(), len as usize)
+    }
+
+    /// Like [`read_buffer`](Self::read_buffer), decoding the bytes as UTF-8.
+    pub fn read_buffer_str(&self, fat: i64) -> crate::Result<String> {
+        let (ptr, len) = abi::unpack(fat);
+        self.read_str(ptr.into(), len as usize)
+    }
+
+    /// Copies `bytes` into a buffer allocated through the guest's `alloc`
+    /// export and returns its fat pointer.
+    ///
+    /// Ownership passes to the guest, which is expected to free it.
+    pub fn write_buffer(&mut self, bytes: &[u8]) -> crate::Result<i64> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        let len = i32::try_from(bytes.len()).map_err(|_| MemoryAccessError::Overflow)?;
+        let (host, mut store) = self.env.data_and_store_mut();
+        let alloc: TypedFunction<i32, i32> = host.exports.get_typed_function(&store, abi::ALLOC)?;
+        let memory = host.memory.clone().ok_or_else(memory::missing)?;
+        let ptr = alloc.call(&mut store.as_store_mut(), len)?;
+        memory.view(&store).write(ptr as u32 as u64, bytes)?;
+        Ok(abi::pack(ptr as u32, len as u32))
+    }
+
+    /// Calls the plugin's export `name` from within this host function, on
+    /// the call already in progress.
+    ///
+    /// This is how a host function calls back into its own plugin: going
+    /// through the [`Plugin`](crate::Plugin) instead fails with
+    /// [`PluginError::Reentrant`](crate::PluginError::Reentrant).
+    pub fn call<Args, Rets>(&mut self, name: &str, args: Args) -> crate::Result<Rets>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let (host, mut store) = self.env.data_and_store_mut();
+        let exports = host.exports.clone();
+        let f = crate::typed_export::<Args, Rets>(&exports, &store, name)?;
+        let mut guest = Guest::new(store.as_store_mut(), &exports);
+        let result = guest.call(&f, args);
+        guest.finish(result, host.panic.take())
+    }
+
+    fn with_memory<R>(
+        &self,
+        f: impl FnOnce(&MemoryView) -> Result<R, MemoryAccessError>,
+    ) -> crate::Result<R> {
+        let view = self.memory().ok_or_else(memory::missing)?;
+        Ok(f(&view)?)
+    }
+}
+
+impl<T> AsStoreRef for Context<'_, T> {
+    fn as_store_ref(&self) -> StoreRef<'_> {
+        self.env.as_store_ref()
+    }
+}
+
+/// Marker for host functions taking only wasm values.
+pub struct WithoutContext;
+
+/// Marker for host functions taking a [`Context`] first.
+pub struct WithContext;
+
+/// Marker for host functions returning a future, taking a [`Context`] first
+/// if `Kind` is [`WithContext`].
+#[cfg(feature = "async")]
+pub struct Async<Kind = WithoutContext>(std::marker::PhantomData<Kind>);
+
+/// Rust closures that can be registered as plugin imports.
+///
+/// Implemented for `Fn` closures taking up to eight wasm values, optionally
+/// preceded by a [`Context`], and returning a [`WasmTypeList`]. With the
+/// `async` feature, closures returning a future of one are accepted too, see
+/// [`Imports::function_async`].
+pub trait IntoHostFunction<T, Args, Rets, Kind>: Send + Sync + 'static {
+    #[doc(hidden)]
+    fn into_function(
+        self: Arc<Self>,
+        store: &mut Store,
+        env: &FunctionEnv<HostEnv<T>>,
+    ) -> wasmer::Function;
+}
+
+macro_rules! impl_into_host_function {
+    ($($arg:ident),*) => {
+        impl<T, F, $($arg,)* Rets> IntoHostFunction<T, ($($arg,)*), Rets, WithoutContext> for F
+        where
+            F: Fn($($arg),*) -> Rets + Send + Sync + 'static,
+            $($arg: FromToNativeWasmType + 'static,)*
+            Rets: WasmTypeList + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_function(
+                self: Arc<Self>,
+                store: &mut Store,
+                _: &FunctionEnv<HostEnv<T>>,
+            ) -> wasmer::Function {
+                wasmer::Function::new_typed(store, move |$($arg: $arg),*| self($($arg),*))
+            }
+        }
+
+        impl<T, F, $($arg,)* Rets> IntoHostFunction<T, ($($arg,)*), Rets, WithContext> for F
+        where
+            T: Send + 'static,
+            F: Fn(Context<'_, T>, $($arg),*) -> Rets + Send + Sync + 'static,
+            $($arg: FromToNativeWasmType + 'static,)*
+            Rets: WasmTypeList + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_function(
+                self: Arc<Self>,
+                store: &mut Store,
+                env: &FunctionEnv<HostEnv<T>>,
+            ) -> wasmer::Function {
+                wasmer::Function::new_typed_with_env(
+                    store,
+                    env,
+                    move |env: FunctionEnvMut<HostEnv<T>>, $($arg: $arg),*| {
+                        self(Context::new(env), $($arg),*)
+                    },
+                )
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<T, F, Fut, $($arg,)* Rets> IntoHostFunction<T, ($($arg,)*), Rets, Async> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: std::future::Future<Output = Rets> + Send + 'static,
+            $($arg: FromToNativeWasmType + 'static,)*
+            Rets: WasmTypeList + Send + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_function(
+                self: Arc<Self>,
+                store: &mut Store,
+                _: &FunctionEnv<HostEnv<T>>,
+            ) -> wasmer::Function {
+                wasmer::Function::new_typed(store, move |$($arg: $arg),*| {
+                    crate::runtime::wait(self($($arg),*))
+                })
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<T, F, Fut, $($arg,)* Rets> IntoHostFunction<T, ($($arg,)*), Rets, Async<WithContext>>
+            for F
+        where
+            T: Send + 'static,
+            F: Fn(Context<'_, T>, $($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: std::future::Future<Output = Rets> + Send + 'static,
+            $($arg: FromToNativeWasmType + 'static,)*
+            Rets: WasmTypeList + Send + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_function(
+                self: Arc<Self>,
+                store: &mut Store,
+                env: &FunctionEnv<HostEnv<T>>,
+            ) -> wasmer::Function {
+                wasmer::Function::new_typed_with_env(
+                    store,
+                    env,
+                    move |env: FunctionEnvMut<HostEnv<T>>, $($arg: $arg),*| {
+                        crate::runtime::wait(self(Context::new(env), $($arg),*))
+                    },
+                )
+            }
+        }
+    };
+}
+
+impl_into_host_function!();
+impl_into_host_function!(A1);
+impl_into_host_function!(A1, A2);
+impl_into_host_function!(A1, A2, A3);
+impl_into_host_function!(A1, A2, A3, A4);
+impl_into_host_function!(A1, A2, A3, A4, A5);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7);
+impl_into_host_function!(A1, A2, A3, A4, A5, A6, A7, A8);
@@ -1,84 +1,551 @@
-use std::{cell::RefCell, ops::Deref, path::Path};
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    ops::{Deref, RangeInclusive},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use wasmer::{imports, FunctionType, Instance, Module, Store, WasmTypeList};
+use serde::{de::DeserializeOwned, Serialize};
+use wasmer::{
+    AsStoreMut, AsStoreRef, Exports, FunctionEnv, Instance, MemoryAccessError, MemoryView, Module,
+    Store,
+};
+
+mod abi;
+mod artifact;
+mod builder;
+mod cache;
+pub mod codec;
+mod deadline;
+mod imports;
+mod limits;
+mod manager;
+mod manifest;
+mod memory;
+mod pool;
+mod reload;
+#[cfg(any(feature = "async", feature = "wasi"))]
+mod runtime;
+mod shared;
+mod tunables;
+#[cfg(feature = "wasi")]
+mod wasi;
+
+use abi::Guest;
+pub use abi::{FromGuest, IntoGuest, Params, Results};
+pub use builder::{Compiler, PluginBuilder};
+use codec::Codec;
+use imports::HostEnv;
+pub use imports::{Context, Imports, IntoHostFunction};
+pub use limits::Limits;
+pub use manager::{LoadReport, PluginManager};
+pub use manifest::PluginManifest;
+pub use plugged_macros::interface;
+pub use pool::{PluginPool, PoolConfig, PooledPlugin};
+pub use reload::{Reload, WatchedPlugin};
+pub use shared::{SharedFunction, SharedPlugin};
+#[cfg(feature = "wasi")]
+pub use wasi::Wasi;
+/// Types describing a plugin's exports.
+pub use wasmer::{ExportType, ExternType, FunctionType, GlobalType, MemoryType, TableType, Type};
 
 #[derive(Debug, thiserror::Error)]
 pub enum PluginError {
+    #[error("Plugin call was cancelled")]
+    Cancelled,
+    #[error("Async host functions can't block a current-thread runtime, use `call_async`")]
+    CurrentThreadRuntime,
+    #[error("Failed to decode plugin output")]
+    Decode(#[source] codec::BoxError),
+    #[error("Failed to encode plugin input")]
+    Encode(#[source] codec::BoxError),
     #[error(transparent)]
     Export(#[from] wasmer::ExportError),
+    #[error("Plugin uses ABI version {plugin}, but the host supports {host:?}")]
+    IncompatibleAbi {
+        plugin: u32,
+        host: RangeInclusive<u32>,
+    },
+    #[error("Precompiled plugin targets {artifact}, but the host is {host}")]
+    IncompatibleArtifact { artifact: String, host: String },
     #[error(transparent)]
     Load(#[from] anyhow::Error),
     #[error(transparent)]
+    Memory(#[from] wasmer::MemoryAccessError),
+    #[error("Plugin ran out of fuel")]
+    OutOfFuel,
+    #[error("Plugin panicked: {0}")]
+    Panic(String),
+    #[error("Plugin was poisoned by an interrupted call")]
+    Poisoned,
+    #[error("No pooled plugin became available within {waited:?}")]
+    PoolTimeout { waited: Duration },
+    #[error("Plugin was called again from within one of its own calls")]
+    Reentrant,
+    #[error(transparent)]
     Runtime(#[from] wasmer::RuntimeError),
+    #[error("Plugin call timed out after {elapsed:?}")]
+    Timeout { elapsed: Duration },
     #[error("Expected function signature {expected} but got {actual}")]
     TypeMismatch {
         actual: FunctionType,
         expected: FunctionType,
     },
+    #[error("Plugin can't be interrupted, load it with `Limits::timeout` or `Limits::fuel`")]
+    Uninterruptible,
+    #[error("No plugin named `{0}` is loaded")]
+    UnknownPlugin(String),
+    #[error("Plugin was loaded without fuel metering")]
+    Unmetered,
 }
 
 pub type Result<T> = std::result::Result<T, PluginError>;
 
-pub struct Plugin {
+/// Custom section the guest SDK records exported signatures in.
+const SIGNATURES: &str = "plugged.signatures";
+
+/// Rust signature of a function exported with `#[plugged::export]`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct Signature {
+    pub name: String,
+    /// Argument names and types as written in the plugin's source.
+    pub params: Vec<(String, String)>,
+    /// Result type as written in the plugin's source, if any.
+    pub result: Option<String>,
+}
+
+/// A loaded plugin instance with host state of type `T`.
+pub struct Plugin<T = ()> {
+    module: Module,
+    manifest: Option<PluginManifest>,
     instance: Instance,
+    env: FunctionEnv<HostEnv<T>>,
+    limits: Limits,
+    deadline: Option<FunctionEnv<deadline::Deadline>>,
+    poisoned: Cell<bool>,
+    /// Whether a call trapped, which may leave the guest's memory half
+    /// updated.
+    trapped: Cell<bool>,
+    memory_usage: Arc<tunables::MemoryUsage>,
+    #[cfg(feature = "wasi")]
+    wasi: Option<wasi::WasiState>,
     store: RefCell<Store>,
 }
 
 impl Plugin {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let bytes = std::fs::read(path.as_ref()).map_err(anyhow::Error::from)?;
-        Self::from_bytes(bytes)
+        PluginBuilder::new().load(path)
     }
 
     pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self> {
-        let store = RefCell::new(Store::default());
-        let module = Module::new(&store.borrow(), bytes).map_err(anyhow::Error::from)?;
-        let import_objects = imports! {};
-        let instance = Instance::new(&mut store.borrow_mut(), &module, &import_objects)
-            .map_err(anyhow::Error::from)?;
+        PluginBuilder::new().load_bytes(bytes)
+    }
+
+    pub fn with_imports(path: impl AsRef<Path>, imports: Imports) -> Result<Self> {
+        PluginBuilder::new().imports(imports).load(path)
+    }
+
+    pub fn from_bytes_with_imports(bytes: impl AsRef<[u8]>, imports: Imports) -> Result<Self> {
+        PluginBuilder::new().imports(imports).load_bytes(bytes)
+    }
+
+    /// Loads the plugin from a `.wasm` or `.wat` file and reloads it whenever
+    /// the file changes, see [`WatchedPlugin`].
+    pub fn watch(path: impl Into<PathBuf>) -> Result<WatchedPlugin> {
+        PluginBuilder::new().watch(path, Duration::from_millis(500))
+    }
+
+    /// Compiles wasm or wat source to native code, which
+    /// [`from_precompiled`](Self::from_precompiled) loads without compiling.
+    pub fn precompile(bytes: impl AsRef<[u8]>) -> Result<Vec<u8>> {
+        PluginBuilder::new().precompile(bytes)
+    }
+
+    /// Loads a plugin compiled by [`precompile`](Self::precompile).
+    ///
+    /// # Safety
+    ///
+    /// See [`PluginBuilder::load_precompiled`].
+    pub unsafe fn from_precompiled(bytes: impl AsRef<[u8]>) -> Result<Self> {
+        PluginBuilder::new().load_precompiled(bytes)
+    }
+
+    /// Starts configuring a plugin, see [`PluginBuilder`].
+    pub fn builder() -> PluginBuilder {
+        PluginBuilder::new()
+    }
+}
+
+impl<T: Send + 'static> Plugin<T> {
+    pub fn with_state(path: impl AsRef<Path>, data: T, imports: Imports<T>) -> Result<Self> {
+        PluginBuilder::with_state(data).imports(imports).load(path)
+    }
+
+    /// Instantiates the plugin with its own host state `data`, which host
+    /// functions reach through their [`Context`].
+    pub fn from_bytes_with_state(
+        bytes: impl AsRef<[u8]>,
+        data: T,
+        imports: Imports<T>,
+    ) -> Result<Self> {
+        PluginBuilder::with_state(data)
+            .imports(imports)
+            .load_bytes(bytes)
+    }
+
+    pub fn with_limits(
+        path: impl AsRef<Path>,
+        data: T,
+        imports: Imports<T>,
+        limits: Limits,
+    ) -> Result<Self> {
+        PluginBuilder::with_state(data)
+            .imports(imports)
+            .limits(limits)
+            .load(path)
+    }
+
+    /// Like [`from_bytes_with_state`](Self::from_bytes_with_state), enforcing
+    /// `limits` on the plugin.
+    pub fn from_bytes_with_limits(
+        bytes: impl AsRef<[u8]>,
+        data: T,
+        imports: Imports<T>,
+        limits: Limits,
+    ) -> Result<Self> {
+        PluginBuilder::with_state(data)
+            .imports(imports)
+            .limits(limits)
+            .load_bytes(bytes)
+    }
+
+    /// Borrows the host state, failing with [`PluginError::Reentrant`] from
+    /// a host function during a call, which should use [`Context::data`]
+    /// instead. The same goes for [`data_mut`](Self::data_mut) and
+    /// [`fuel`](Self::fuel).
+    pub fn data(&self) -> Result<Ref<'_, T>> {
+        let store = self
+            .store
+            .try_borrow()
+            .map_err(|_| PluginError::Reentrant)?;
+        Ok(Ref::map(store, |store| &self.env.as_ref(store).data))
+    }
+
+    pub fn data_mut(&self) -> Result<RefMut<'_, T>> {
+        Ok(RefMut::map(self.store()?, |store| {
+            &mut self.env.as_mut(store).data
+        }))
+    }
+
+    /// Returns the fuel left, or `None` if the plugin isn't metered.
+    pub fn fuel(&self) -> Result<Option<u64>> {
+        if self.limits.fuel.is_none() {
+            return Ok(None);
+        }
+        let mut store = self.store()?;
+        Ok(Some(limits::fuel(&mut *store, &self.instance.exports)))
+    }
+
+    /// Replaces the fuel left with `fuel`.
+    ///
+    /// With [`Limits::fuel_per_call`] this only lasts until the next call.
+    pub fn set_fuel(&self, fuel: u64) -> Result<()> {
+        self.limits.fuel.ok_or(PluginError::Unmetered)?;
+        let mut store = self.store()?;
+        limits::set_fuel(&mut *store, &self.instance.exports, fuel);
+        Ok(())
+    }
+
+    /// Adds `fuel` to the fuel left, e.g. after a call failed with
+    /// [`PluginError::OutOfFuel`].
+    pub fn refuel(&self, fuel: u64) -> Result<()> {
+        self.limits.fuel.ok_or(PluginError::Unmetered)?;
+        let mut store = self.store()?;
+        let fuel = limits::fuel(&mut *store, &self.instance.exports).saturating_add(fuel);
+        limits::set_fuel(&mut *store, &self.instance.exports, fuel);
+        Ok(())
+    }
+
+    /// Tells whether a call was interrupted by a timeout or a cancellation,
+    /// after which every call fails with [`PluginError::Poisoned`].
+    ///
+    /// The guest may have been stopped anywhere, so its memory can't be
+    /// trusted anymore. Reading it is still allowed.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.get()
+    }
+
+    /// Moves the plugin behind a mutex so it can be called from several
+    /// threads, see [`SharedPlugin`].
+    pub fn into_shared(self) -> SharedPlugin<T> {
+        SharedPlugin::new(self)
+    }
+
+    /// Returns the most linear memory the plugin held at once, in bytes.
+    pub fn peak_memory(&self) -> u64 {
+        self.memory_usage.peak()
+    }
+
+    /// Drains what the plugin wrote to stdout since the last call, if
+    /// [`Wasi::capture_stdout`] is set.
+    #[cfg(feature = "wasi")]
+    pub fn take_stdout(&self) -> Vec<u8> {
+        let wasi = self.wasi.as_ref();
+        wasi.map(wasi::WasiState::take_stdout).unwrap_or_default()
+    }
+
+    /// Drains what the plugin wrote to stderr since the last call, if
+    /// [`Wasi::capture_stderr`] is set.
+    #[cfg(feature = "wasi")]
+    pub fn take_stderr(&self) -> Vec<u8> {
+        let wasi = self.wasi.as_ref();
+        wasi.map(wasi::WasiState::take_stderr).unwrap_or_default()
+    }
+
+    memory::memory_accessors!();
+
+    fn with_memory<R>(
+        &self,
+        f: impl FnOnce(&MemoryView) -> std::result::Result<R, MemoryAccessError>,
+    ) -> Result<R> {
+        let store = self
+            .store
+            .try_borrow()
+            .map_err(|_| PluginError::Reentrant)?;
+        let memory = self.env.as_ref(&*store).memory.as_ref();
+        let view = memory.ok_or_else(memory::missing)?.view(&*store);
+        Ok(f(&view)?)
+    }
+
+    pub fn function<Args, Rets>(&self, name: impl AsRef<str>) -> Result<Function<'_, Args, Rets>>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let f = self.typed_export::<Args, Rets>(name.as_ref())?;
+        Ok(Function::new(move |args, timeout| {
+            self.invoke(&f, args, timeout, None)
+        }))
+    }
+
+    /// Looks up `name` once for the proxies of `#[plugged::interface]`,
+    /// which call it through [`call_export`](Self::call_export).
+    #[doc(hidden)]
+    pub fn checked_export<Args, Rets>(&self, name: &str) -> Result<CheckedExport>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        self.typed_export::<Args, Rets>(name).map(CheckedExport)
+    }
+
+    #[doc(hidden)]
+    pub fn call_export<Args, Rets>(&self, export: &CheckedExport, args: Args) -> Result<Rets>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        self.invoke(&export.0, args, None, None)
+    }
+
+    /// Looks up the exported function `name`, checking it takes `Args` and
+    /// returns `Rets`.
+    fn typed_export<Args, Rets>(&self, name: &str) -> Result<wasmer::Function>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        typed_export::<Args, Rets>(&self.instance.exports, &*self.store()?, name)
+    }
 
-        Ok(Self { instance, store })
+    /// Borrows the store, failing if a call is already running on it.
+    fn store(&self) -> Result<RefMut<'_, Store>> {
+        self.store
+            .try_borrow_mut()
+            .map_err(|_| PluginError::Reentrant)
     }
 
-    pub fn function<Args, Rets>(&self, name: impl AsRef<str>) -> Result<Function<Args, Rets>>
+    /// Calls `f`, an export checked by [`typed_export`](Self::typed_export).
+    fn invoke<Args, Rets>(
+        &self,
+        f: &wasmer::Function,
+        args: Args,
+        timeout: Option<Duration>,
+        cancel: Option<&Arc<deadline::Cancel>>,
+    ) -> Result<Rets>
     where
-        Args: WasmTypeList,
-        Rets: WasmTypeList,
+        Args: Params,
+        Rets: Results,
     {
-        let f = self
-            .instance
-            .exports
-            .get_function(name.as_ref())
-            .map_err(PluginError::Export)?;
-
-        let actual = f.ty(&self.store.borrow());
-        let expected = FunctionType::new(Args::wasm_types(), Rets::wasm_types());
-        if actual != expected {
-            return Err(PluginError::TypeMismatch { actual, expected });
+        if self.poisoned.get() {
+            return Err(PluginError::Poisoned);
+        }
+        if cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+            return Err(PluginError::Cancelled);
+        }
+        let timeout = timeout.or(self.limits.timeout);
+        if timeout.is_some() && self.deadline.is_none() {
+            return Err(PluginError::Uninterruptible);
         }
 
-        let f = |args: Args| -> Result<Rets> {
-            let store = &mut self.store.borrow_mut();
-            let args = unsafe { args.into_array(store) }.as_mut().into();
-            let result = {
-                let res = f.call_raw(store, args)?;
-                let res = res.iter().map(|v| v.as_raw(store)).collect::<Vec<_>>();
-                unsafe { Rets::from_slice(store, res.as_ref()).unwrap_unchecked() }
-            };
-            Ok(result)
+        #[cfg(feature = "wasi")]
+        let _runtime = self.wasi.as_ref().map(wasi::WasiState::enter);
+        let store = &mut self.store()?;
+        if let Some(limits::Fuel::Call(fuel)) = self.limits.fuel {
+            limits::set_fuel(&mut **store, &self.instance.exports, fuel);
+        }
+        let start = Instant::now();
+        // Plugins without deadline checks can't be interrupted, cancelled
+        // calls run on.
+        if let Some(deadline) = &self.deadline {
+            let at = timeout.map(|timeout| start + timeout);
+            deadline.as_mut(&mut **store).start(at, cancel);
+        }
+        let mut guest = Guest::new(store.as_store_mut(), &self.instance.exports);
+        let result = guest.call(f, args);
+        let timed_out = match &self.deadline {
+            Some(deadline) => deadline.as_mut(guest.store()).finish(),
+            None => false,
         };
+        let panic = self.env.as_mut(guest.store()).panic.take();
+        if timed_out {
+            self.poisoned.set(true);
+            if cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+                return Err(PluginError::Cancelled);
+            }
+            let elapsed = start.elapsed();
+            return Err(PluginError::Timeout { elapsed });
+        }
+        let result = guest.finish(result, panic);
+        if let Err(PluginError::Panic(_) | PluginError::Runtime(_) | PluginError::OutOfFuel) =
+            result
+        {
+            self.trapped.set(true);
+        }
+        result
+    }
+
+    /// Lists the functions, memories, globals and tables the plugin exports
+    /// with their types, in the order the plugin declares them.
+    pub fn exports(&self) -> Vec<ExportType> {
+        // Metering adds its globals to the module's exports; they're ours.
+        self.module
+            .exports()
+            .filter(|export| ![limits::REMAINING, limits::EXHAUSTED].contains(&export.name()))
+            .collect()
+    }
 
-        Ok(Function::new(f))
+    /// Returns the manifest recorded by the guest SDK's `manifest!`, if the
+    /// plugin has one.
+    pub fn manifest(&self) -> Option<&PluginManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Returns the signatures recorded by functions exported with the guest
+    /// SDK's `#[plugged::export]`, in no particular order.
+    pub fn signatures(&self) -> Result<Vec<Signature>> {
+        let mut signatures = Vec::new();
+        for section in self.module.custom_sections(SIGNATURES) {
+            let entries = serde_json::Deserializer::from_slice(&section).into_iter();
+            for signature in entries {
+                signatures.push(signature.map_err(|e| PluginError::Decode(e.into()))?);
+            }
+        }
+        Ok(signatures)
+    }
+
+    /// Calls `name` with `input` encoded as JSON and decodes its JSON output.
+    ///
+    /// The plugin function takes and returns a single buffer, see
+    /// [`Params`] for how buffers are passed.
+    pub fn call_serde<In, Out>(&self, name: impl AsRef<str>, input: &In) -> Result<Out>
+    where
+        In: Serialize + ?Sized,
+        Out: DeserializeOwned,
+    {
+        self.call_serde_with(&codec::Json, name, input)
+    }
+
+    /// Like [`call_serde`](Self::call_serde) with an explicit [`Codec`].
+    pub fn call_serde_with<C, In, Out>(
+        &self,
+        codec: &C,
+        name: impl AsRef<str>,
+        input: &In,
+    ) -> Result<Out>
+    where
+        C: Codec,
+        In: Serialize + ?Sized,
+        Out: DeserializeOwned,
+    {
+        let input = codec.encode(input).map_err(PluginError::Encode)?;
+        let f = self.function::<&[u8], Vec<u8>>(name)?;
+        let output = f(&input)?;
+        codec.decode(&output).map_err(PluginError::Decode)
+    }
+}
+
+/// Looks up the exported function `name`, checking it takes `Args` and
+/// returns `Rets`.
+pub(crate) fn typed_export<Args, Rets>(
+    exports: &Exports,
+    store: &impl AsStoreRef,
+    name: &str,
+) -> Result<wasmer::Function>
+where
+    Args: Params,
+    Rets: Results,
+{
+    let f = exports.get_function(name).map_err(PluginError::Export)?;
+    let actual = f.ty(store);
+    let expected = FunctionType::new(Args::wasm_types(), Rets::wasm_types());
+    if actual != expected {
+        return Err(PluginError::TypeMismatch { actual, expected });
+    }
+    Ok(f.clone())
+}
+
+#[cfg(feature = "wasi")]
+impl<T> Drop for Plugin<T> {
+    fn drop(&mut self) {
+        if let Some(wasi) = &self.wasi {
+            wasi.cleanup(self.store.get_mut());
+        }
     }
 }
 
+/// Export looked up by [`Plugin::checked_export`].
+#[doc(hidden)]
+pub struct CheckedExport(wasmer::Function);
+
 pub struct Function<'plugin, Args = (), Rets = ()> {
     inner: Box<dyn Fn(Args) -> Result<Rets> + 'plugin>,
+    timed: Rc<dyn Fn(Args, Option<Duration>) -> Result<Rets> + 'plugin>,
 }
 
 impl<'plugin, Args, Rets> Function<'plugin, Args, Rets> {
-    fn new(f: impl Fn(Args) -> Result<Rets> + 'plugin) -> Self {
-        Self { inner: Box::new(f) }
+    fn new(f: impl Fn(Args, Option<Duration>) -> Result<Rets> + 'plugin) -> Self {
+        let timed = Rc::new(f);
+        let inner = {
+            let timed = timed.clone();
+            Box::new(move |args| timed(args, None))
+        };
+        Self { inner, timed }
+    }
+
+    /// Calls the function, failing with [`PluginError::Timeout`] if it runs
+    /// longer than `timeout`.
+    ///
+    /// The guest only checks its deadline when loaded with [`Limits::fuel`]
+    /// or [`Limits::timeout`], otherwise this fails with
+    /// [`PluginError::Uninterruptible`]. It checks it while running its own
+    /// code, so a guest waiting in a host function is only interrupted once
+    /// that returns. An interrupted plugin is poisoned, see
+    /// [`Plugin::is_poisoned`].
+    pub fn call_timeout(&self, args: Args, timeout: Duration) -> Result<Rets> {
+        (self.timed)(args, Some(timeout))
     }
 }
 
@@ -114,6 +581,51 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn exports() -> Result<()> {
+        let plugin = Plugin::from_bytes(
+            r#"(module
+                (memory (export "memory") 1 2)
+                (global (export "answer") i32 (i32.const 42))
+                (table (export "table") 1 funcref)
+                (func (export "add") (param i32 i32) (result i32)
+                    (i32.add (local.get 0) (local.get 1))))"#,
+        )?;
+        let exports = plugin.exports();
+        let names: Vec<_> = exports.iter().map(ExportType::name).collect();
+        assert_eq!(names, ["memory", "answer", "table", "add"]);
+        let ExternType::Memory(memory) = exports[0].ty() else {
+            panic!("expected a memory");
+        };
+        assert_eq!(
+            (memory.minimum.0, memory.maximum.map(|max| max.0)),
+            (1, Some(2))
+        );
+        let ExternType::Global(global) = exports[1].ty() else {
+            panic!("expected a global");
+        };
+        assert_eq!(global.ty, Type::I32);
+        let ExternType::Table(table) = exports[2].ty() else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.ty, Type::FuncRef);
+        assert_eq!(
+            exports[3].ty(),
+            &ExternType::Function(FunctionType::new([Type::I32; 2], [Type::I32]))
+        );
+
+        let metered = Plugin::builder()
+            .limits(Limits::new().fuel(1_000))
+            .load("./examples/plugins/add.wat")?;
+        let names: Vec<_> = metered
+            .exports()
+            .iter()
+            .map(|e| e.name().to_owned())
+            .collect();
+        assert_eq!(names, ["add"]);
+        Ok(())
+    }
+
     #[test]
     fn types_mismatch_fail() -> Result<()> {
         let plugin = Plugin::new("./examples/plugins/add.wat")?;
@@ -125,4 +637,325 @@ mod tests {
         assert!(matches!(result, Err(PluginError::TypeMismatch { .. })));
         Ok(())
     }
+
+    #[test]
+    fn host_function() -> Result<()> {
+        let imports = Imports::new().function("env", "double", |x: i32| x * 2);
+        let plugin = Plugin::with_imports("./examples/plugins/host.wat", imports)?;
+        let f = plugin.function::<i32, i32>("quadruple")?;
+        assert_eq!(f(3)?, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_host_function_fail() {
+        let result = Plugin::new("./examples/plugins/host.wat");
+        assert!(matches!(result, Err(PluginError::Load(_))));
+    }
+
+    #[test]
+    fn reentrant() -> Result<()> {
+        let imports = Imports::new().function("env", "callback", |mut ctx: Context<()>, x: i32| {
+            ctx.call::<i32, i32>("inner", x).unwrap() * 10
+        });
+        let plugin = Plugin::with_imports("./examples/plugins/reentrant.wat", imports)?;
+        assert_eq!(plugin.function::<i32, i32>("outer")?(1)?, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn reentrant_fail() -> Result<()> {
+        thread_local! {
+            static PLUGIN: RefCell<Option<Rc<Plugin>>> = const { RefCell::new(None) };
+        }
+        let imports = Imports::new().function("env", "callback", |x: i32| {
+            let plugin = PLUGIN.with(|plugin| plugin.borrow().clone().unwrap());
+            let result = plugin.function::<i32, i32>("inner");
+            assert!(matches!(result, Err(PluginError::Reentrant)));
+            assert!(matches!(
+                plugin.read_bytes(0, 1),
+                Err(PluginError::Reentrant)
+            ));
+            assert!(matches!(plugin.data(), Err(PluginError::Reentrant)));
+            assert!(matches!(plugin.data_mut(), Err(PluginError::Reentrant)));
+            x
+        });
+        let plugin = Rc::new(Plugin::with_imports(
+            "./examples/plugins/reentrant.wat",
+            imports,
+        )?);
+        PLUGIN.with(|cell| *cell.borrow_mut() = Some(plugin.clone()));
+        assert_eq!(plugin.function::<i32, i32>("outer")?(1)?, 1);
+        PLUGIN.with(|cell| cell.borrow_mut().take());
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_host_function() -> Result<()> {
+        let imports = Imports::new().function_async("env", "double", |x: i32| async move {
+            tokio::task::yield_now().await;
+            x * 2
+        });
+        let plugin = Plugin::with_imports("./examples/plugins/host.wat", imports)?;
+        assert_eq!(plugin.function::<i32, i32>("quadruple")?(3)?, 12);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_host_function_call_async() -> Result<()> {
+        let imports = Imports::new().function_async("env", "double", |x: i32| async move {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            x * 2
+        });
+        let plugin = Plugin::with_imports("./examples/plugins/host.wat", imports)?.into_shared();
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        assert_eq!(quadruple.call_async(3).await?, 12);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_host_function_context() -> Result<()> {
+        let imports =
+            Imports::new().function_async("env", "double", |mut ctx: Context<u32>, x: i32| {
+                *ctx.data_mut() += 1;
+                async move {
+                    tokio::task::yield_now().await;
+                    x * 2
+                }
+            });
+        let plugin = Plugin::with_state("./examples/plugins/host.wat", 0, imports)?.into_shared();
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        assert_eq!(quadruple.call_async(3).await?, 12);
+        assert_eq!(*plugin.lock().data()?, 2);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_host_function_current_thread_fail() -> Result<()> {
+        let imports = Imports::new().function_async("env", "double", |x: i32| async move { x * 2 });
+        let plugin = Plugin::with_imports("./examples/plugins/host.wat", imports)?;
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        assert!(matches!(
+            quadruple(3),
+            Err(PluginError::CurrentThreadRuntime)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn host_state() -> Result<()> {
+        let imports = Imports::new().function("env", "double", |mut ctx: Context<u32>, x: i32| {
+            *ctx.data_mut() += 1;
+            x * 2
+        });
+        let plugin = Plugin::with_state("./examples/plugins/host.wat", 0, imports)?;
+        let f = plugin.function::<i32, i32>("quadruple")?;
+        assert_eq!(f(3)?, 12);
+        assert_eq!(*plugin.data()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn host_memory() -> Result<()> {
+        let imports =
+            Imports::new().function("env", "sum", |ctx: Context<()>, ptr: u32, len: u32| {
+                let mut bytes = vec![0; len as usize];
+                ctx.memory().unwrap().read(ptr.into(), &mut bytes).unwrap();
+                bytes.iter().map(|&b| i32::from(b)).sum::<i32>()
+            });
+        let plugin = Plugin::with_imports("./examples/plugins/context.wat", imports)?;
+        let f = plugin.function::<(), i32>("sum_bytes")?;
+        assert_eq!(f(())?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn guest_sdk() -> Result<()> {
+        let imports = Imports::new()
+            .function("env", "name", |mut ctx: Context<Vec<String>>| {
+                ctx.write_buffer(b"world").unwrap()
+            })
+            .function(
+                "env",
+                "log",
+                |mut ctx: Context<Vec<String>>, message: i64| {
+                    let message = ctx.read_buffer_str(message).unwrap();
+                    ctx.data_mut().push(message);
+                },
+            );
+        let plugin = Plugin::with_state(
+            "./examples/plugins/greet.wasm/target/wasm32-unknown-unknown/release/greet.wasm",
+            Vec::new(),
+            imports,
+        )?;
+        let greet = plugin.function::<&str, String>("greet")?;
+        assert_eq!(greet("Hello")?, "Hello, world");
+        assert_eq!(*plugin.data()?, ["Hello, world"]);
+
+        let fail = plugin.function::<(), ()>("fail")?;
+        let Err(PluginError::Panic(message)) = fail(()) else {
+            panic!("expected a panic");
+        };
+        assert!(message.contains("nothing to see here"));
+        Ok(())
+    }
+
+    #[test]
+    fn panic_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/panic.wat")?;
+        let fail = plugin.function::<(), ()>("fail")?;
+        assert!(matches!(fail(()), Err(PluginError::Panic(message)) if message == "boom"));
+
+        let trap = plugin.function::<(), ()>("trap")?;
+        assert!(matches!(trap(()), Err(PluginError::Runtime(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn panic_release() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let fail = plugin.function::<&str, ()>("fail")?;
+        assert!(matches!(fail("boom"), Err(PluginError::Panic(message)) if message == "boom"));
+        assert_eq!(plugin.function::<(), i32>("freed")?(())?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn guest_export() -> Result<()> {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        let plugin = Plugin::new(
+            "./examples/plugins/export.wasm/target/wasm32-unknown-unknown/release/export.wasm",
+        )?;
+        let add = plugin.function::<(i32, i32), i32>("add")?;
+        assert_eq!(add((42, 1))?, 43);
+        let greet = plugin.function::<&str, String>("greet")?;
+        assert_eq!(greet("world")?, "Hello, world");
+        let double = plugin.function::<i32, i32>("double")?;
+        assert_eq!(double(21)?, 42);
+
+        let translate = plugin.function::<(Vec<u8>, i32), Vec<u8>>("translate")?;
+        let point = serde_json::to_vec(&Point { x: 1, y: 2 }).unwrap();
+        let point: Point = serde_json::from_slice(&translate((point, 3))?).unwrap();
+        assert_eq!(point, Point { x: 4, y: 5 });
+        let point: Point = plugin.call_serde("offset", &(Point { x: 1, y: 2 }, 3))?;
+        assert_eq!(point, Point { x: 4, y: 5 });
+
+        let checked_div = plugin.function::<(i32, i32), Vec<u8>>("checked_div")?;
+        let result: std::result::Result<i32, String> =
+            serde_json::from_slice(&checked_div((1, 0))?).unwrap();
+        assert_eq!(result, Err("division by zero".into()));
+
+        let mut signatures = plugin.signatures()?;
+        signatures.sort_by(|a, b| a.name.cmp(&b.name));
+        let names = signatures
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "add",
+                "checked_div",
+                "double",
+                "greet",
+                "offset",
+                "translate"
+            ]
+        );
+        assert_eq!(
+            signatures[1],
+            Signature {
+                name: "checked_div".into(),
+                params: vec![("a".into(), "i32".into()), ("b".into(), "i32".into())],
+                result: Some("Result<i32,String>".into()),
+            }
+        );
+        assert_eq!(signatures[3].params, [("name".into(), "&str".into())]);
+
+        let manifest = plugin.manifest().unwrap();
+        assert_eq!((&*manifest.name, &*manifest.version), ("export", "0.1.0"));
+        assert_eq!(manifest.authors, ["Jane Doe <jane@example.com>"]);
+        assert_eq!(manifest.host_api.as_deref(), Some("^0.1"));
+        Ok(())
+    }
+
+    #[crate::interface]
+    trait Calculator {
+        fn add(&self, a: i32, b: i32) -> i32;
+    }
+
+    #[crate::interface]
+    trait Greeter {
+        fn greet(&self, name: &str) -> String;
+        fn freed(&self) -> i32;
+    }
+
+    #[test]
+    fn interface() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/add.wat")?;
+        let calculator = CalculatorProxy::bind(&plugin)?;
+        assert_eq!(calculator.add(42, 1)?, 43);
+
+        let plugin = Plugin::new("./examples/plugins/strings.wat")?;
+        let greeter = GreeterProxy::bind(&plugin)?;
+        assert_eq!(greeter.greet("world")?, "Hello, world");
+        assert_eq!(greeter.freed()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn interface_fail() -> Result<()> {
+        #[allow(dead_code)]
+        #[crate::interface]
+        trait WideCalculator {
+            fn add(&self, a: i64, b: i64) -> i64;
+        }
+
+        let plugin = Plugin::new("./examples/plugins/add.wat")?;
+        let result = WideCalculatorProxy::bind(&plugin);
+        assert!(matches!(result, Err(PluginError::TypeMismatch { .. })));
+        let result = GreeterProxy::bind(&plugin);
+        assert!(matches!(result, Err(PluginError::Export(_))));
+        Ok(())
+    }
+
+    #[cfg(feature = "wasi")]
+    #[test]
+    fn wasi() -> Result<()> {
+        let wasi = Wasi::new()
+            .args(["a", "b"])
+            .env("LANG", "C")
+            .map_dir("/examples", "./examples")
+            .capture_stdout()
+            .capture_stderr();
+        let imports = Imports::new().wasi(wasi);
+        let plugin = Plugin::with_imports("./examples/plugins/wasi.wat", imports)?;
+
+        assert_eq!(plugin.function::<(), i32>("args")?(())?, 3);
+        assert_eq!(plugin.function::<(), i32>("envs")?(())?, 1);
+        assert_eq!(plugin.function::<(), i32>("preopened")?(())?, 0);
+
+        let hello = plugin.function::<(), i32>("hello")?;
+        assert_eq!(hello(())?, 0);
+        assert_eq!(plugin.take_stdout(), b"hello\n");
+        assert_eq!(plugin.take_stderr(), b"oops\n");
+        assert!(plugin.take_stdout().is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "wasi")]
+    #[test]
+    fn wasi_missing_fail() {
+        let result = Plugin::new("./examples/plugins/wasi.wat");
+        assert!(matches!(result, Err(PluginError::Load(_))));
+    }
 }
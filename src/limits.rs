@@ -0,0 +1,348 @@
+//! Resource limits enforced on a plugin.
+
+use std::{sync::Arc, time::Duration};
+
+use wasmer::{
+    AsStoreMut, BaseTunables, CompilerConfig, Engine, EngineBuilder, Exports, Features,
+    NativeEngineExt, Pages, WASM_PAGE_SIZE,
+};
+use wasmer_middlewares::Metering;
+
+use crate::{
+    deadline::Checkpoints,
+    tunables::{MemoryUsage, Tunables},
+};
+
+/// Resource limits of a plugin, applied when it is loaded.
+///
+/// ```
+/// use plugged::{Imports, Limits, Plugin};
+///
+/// let limits = Limits::new().fuel_per_call(10_000);
+/// let plugin = Plugin::with_limits("./examples/plugins/add.wat", (), Imports::new(), limits)?;
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub(crate) fuel: Option<Fuel>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) memory: Option<Pages>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Fuel {
+    /// Shared by all calls until refueled.
+    Plugin(u64),
+    /// Reset before every call.
+    Call(u64),
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Meters every executed instruction against a budget of `fuel` shared
+    /// by all calls, see [`Plugin::refuel`](crate::Plugin::refuel).
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(Fuel::Plugin(fuel));
+        self
+    }
+
+    /// Meters every executed instruction against a budget of `fuel`
+    /// restored before each call.
+    pub fn fuel_per_call(mut self, fuel: u64) -> Self {
+        self.fuel = Some(Fuel::Call(fuel));
+        self
+    }
+
+    /// Interrupts calls running longer than `timeout`, see
+    /// [`Function::call_timeout`](crate::Function::call_timeout).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Caps the linear memory of the plugin at `bytes`, rounded down to
+    /// whole pages.
+    ///
+    /// Growing past it makes `memory.grow` fail in the guest, and loading a
+    /// plugin needing more memory to start with fails.
+    pub fn memory(self, bytes: u64) -> Self {
+        let pages = bytes / WASM_PAGE_SIZE as u64;
+        self.memory_pages(pages.try_into().unwrap_or(u32::MAX))
+    }
+
+    /// Caps the linear memory of the plugin at `pages` of 64 KiB.
+    pub fn memory_pages(mut self, pages: u32) -> Self {
+        self.memory = Some(Pages(pages));
+        self
+    }
+
+    /// Fuel the metering middleware starts with, if any is needed.
+    pub(crate) fn metering(&self) -> Option<u64> {
+        self.fuel
+            .map(|(Fuel::Plugin(fuel) | Fuel::Call(fuel))| fuel)
+    }
+
+    /// Whether calls can be interrupted, see [`deadline`](crate::deadline).
+    pub(crate) fn interruptible(&self) -> bool {
+        self.fuel.is_some() || self.timeout.is_some()
+    }
+
+    /// Creates an engine compiling modules with `compiler` under these
+    /// limits, along with the memory usage of its instances.
+    pub(crate) fn engine(
+        &self,
+        mut compiler: Box<dyn CompilerConfig>,
+        features: Option<Features>,
+    ) -> (Engine, Arc<MemoryUsage>) {
+        if let Some(fuel) = self.metering() {
+            compiler.push_middleware(Arc::new(Metering::new(fuel, |_| 1)));
+        }
+        // After metering, which leaves the checks out of the fuel used.
+        if self.interruptible() {
+            compiler.push_middleware(Arc::new(Checkpoints::default()));
+        }
+        let mut engine = Engine::from(EngineBuilder::new(compiler).set_features(features));
+        let tunables = Tunables::new(BaseTunables::for_target(engine.target()), self.memory);
+        let usage = tunables.usage();
+        engine.set_tunables(tunables);
+        (engine, usage)
+    }
+}
+
+/// Globals the metering middleware exports from every metered instance.
+pub(crate) const REMAINING: &str = "wasmer_metering_remaining_points";
+pub(crate) const EXHAUSTED: &str = "wasmer_metering_points_exhausted";
+
+/// Remaining fuel of a metered instance.
+pub(crate) fn fuel(store: &mut impl AsStoreMut, exports: &Exports) -> u64 {
+    if exhausted(store, exports) {
+        return 0;
+    }
+    let remaining = exports.get_global(REMAINING).expect("instance is metered");
+    remaining.get(store).try_into().unwrap()
+}
+
+pub(crate) fn set_fuel(store: &mut impl AsStoreMut, exports: &Exports, fuel: u64) {
+    let remaining = exports.get_global(REMAINING).expect("instance is metered");
+    let exhausted = exports.get_global(EXHAUSTED).expect("instance is metered");
+    remaining.set(store, fuel.into()).unwrap();
+    exhausted.set(store, 0i32.into()).unwrap();
+}
+
+/// Tells whether a metered instance ran out of fuel, always `false` for
+/// unmetered ones.
+pub(crate) fn exhausted(store: &mut impl AsStoreMut, exports: &Exports) -> bool {
+    let exhausted = exports.get_global(EXHAUSTED).ok();
+    exhausted.is_some_and(|exhausted| exhausted.get(store).i32() != Some(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{Compiler, Context, Imports, Limits, Plugin, PluginError, Result};
+
+    #[test]
+    fn fuel() -> Result<()> {
+        let limits = Limits::new().fuel(1_000);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/loop.wat", (), Imports::new(), limits)?;
+        let count = plugin.function::<i32, i32>("count")?;
+        assert_eq!(count(10)?, 10);
+        let left = plugin.fuel()?.unwrap();
+        assert!(left < 1_000);
+
+        let spin = plugin.function::<(), ()>("spin")?;
+        assert!(matches!(spin(()), Err(PluginError::OutOfFuel)));
+        assert_eq!(plugin.fuel()?, Some(0));
+        assert!(matches!(count(1), Err(PluginError::OutOfFuel)));
+
+        plugin.refuel(1_000)?;
+        assert_eq!(count(10)?, 10);
+        plugin.set_fuel(5)?;
+        assert_eq!(plugin.fuel()?, Some(5));
+        Ok(())
+    }
+
+    #[test]
+    fn fuel_per_call() -> Result<()> {
+        let limits = Limits::new().fuel_per_call(1_000);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/loop.wat", (), Imports::new(), limits)?;
+        let count = plugin.function::<i32, i32>("count")?;
+        for _ in 0..10 {
+            assert_eq!(count(50)?, 50);
+        }
+        assert!(matches!(count(1_000), Err(PluginError::OutOfFuel)));
+        assert_eq!(count(50)?, 50);
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_fuel_release() -> Result<()> {
+        let limits = Limits::new().fuel(10_000);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/strings.wat", (), Imports::new(), limits)?;
+        let spin = plugin.function::<&str, ()>("spin")?;
+        assert!(matches!(spin("world"), Err(PluginError::OutOfFuel)));
+        assert_eq!(plugin.fuel()?, Some(0));
+        plugin.refuel(1_000)?;
+        assert_eq!(plugin.function::<(), i32>("freed")?(())?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_fuel_context_call() -> Result<()> {
+        let imports =
+            Imports::new().function("env", "callback", |mut ctx: Context<bool>, x: i32| {
+                let result = ctx.call::<i32, ()>("spin", x);
+                *ctx.data_mut() = matches!(result, Err(PluginError::OutOfFuel));
+                x
+            });
+        let limits = Limits::new().fuel(1_000);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/reentrant.wat", false, imports, limits)?;
+        let outer = plugin.function::<i32, i32>("outer")?;
+        assert!(matches!(outer(1), Err(PluginError::OutOfFuel)));
+        assert!(*plugin.data()?);
+        Ok(())
+    }
+
+    #[test]
+    fn unmetered_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/loop.wat")?;
+        assert_eq!(plugin.fuel()?, None);
+        assert!(matches!(plugin.refuel(1), Err(PluginError::Unmetered)));
+        Ok(())
+    }
+
+    #[test]
+    fn timeout() -> Result<()> {
+        let limits = Limits::new().timeout(Duration::from_millis(50));
+        let plugin =
+            Plugin::with_limits("./examples/plugins/loop.wat", (), Imports::new(), limits)?;
+        let count = plugin.function::<i32, i32>("count")?;
+        assert_eq!(count(1_000)?, 1_000);
+        assert_eq!(plugin.fuel()?, None);
+
+        let spin = plugin.function::<(), ()>("spin")?;
+        let Err(PluginError::Timeout { elapsed }) = spin(()) else {
+            panic!("expected a timeout");
+        };
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(plugin.is_poisoned());
+        assert!(matches!(count(1), Err(PluginError::Poisoned)));
+        Ok(())
+    }
+
+    #[test]
+    fn call_timeout() -> Result<()> {
+        let limits = Limits::new().fuel(u64::MAX);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/loop.wat", (), Imports::new(), limits)?;
+        let count = plugin.function::<i32, i32>("count")?;
+        assert_eq!(count.call_timeout(10, Duration::from_secs(10))?, 10);
+
+        let spin = plugin.function::<(), ()>("spin")?;
+        let result = spin.call_timeout((), Duration::from_millis(10));
+        assert!(matches!(result, Err(PluginError::Timeout { .. })));
+        Ok(())
+    }
+
+    /// Deadline checks shift the function indices of the module, which
+    /// calls, tables and exports must follow.
+    fn deadline_checks(compiler: Compiler) -> Result<()> {
+        let plugin = Plugin::builder()
+            .imports(Imports::new().function("env", "double", |x: i32| x * 2))
+            .limits(Limits::new().timeout(Duration::from_secs(10)))
+            .compiler(compiler)
+            .load_bytes(
+                r#"(module
+                    (import "env" "double" (func $double (param i32) (result i32)))
+                    (type $unary (func (param i32) (result i32)))
+                    (table 2 funcref)
+                    (elem (i32.const 0) $inc $depth)
+                    (global $started (mut i32) (i32.const 0))
+                    (start $start)
+                    (func $start (global.set $started (i32.const 1)))
+                    (func $inc (param i32) (result i32)
+                        (i32.add (local.get 0) (i32.const 1)))
+                    (func $depth (param i32) (result i32)
+                        (if (result i32) (i32.eqz (local.get 0))
+                            (then (global.get $started))
+                            (else (i32.add
+                                (call $depth (i32.sub (local.get 0) (i32.const 1)))
+                                (i32.const 1)))))
+                    (func (export "run") (param i32) (result i32)
+                        (call $double
+                            (call_indirect (type $unary) (local.get 0) (i32.const 0))))
+                    (func (export "depth") (param i32) (result i32)
+                        (call_indirect (type $unary) (local.get 0) (i32.const 1)))
+                    (func (export "spin")
+                        (loop $forever (br $forever))))"#,
+            )?;
+        assert_eq!(plugin.function::<i32, i32>("run")?(20)?, 42);
+        // Enough function entries to go through several checks.
+        let depth = plugin.function::<i32, i32>("depth")?;
+        for _ in 0..1_000 {
+            assert_eq!(depth(100)?, 101);
+        }
+        let spin = plugin.function::<(), ()>("spin")?;
+        let result = spin.call_timeout((), Duration::from_millis(10));
+        assert!(matches!(result, Err(PluginError::Timeout { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_checks_cranelift() -> Result<()> {
+        deadline_checks(Compiler::Cranelift)
+    }
+
+    #[cfg(feature = "singlepass")]
+    #[test]
+    fn deadline_checks_singlepass() -> Result<()> {
+        deadline_checks(Compiler::Singlepass)
+    }
+
+    #[test]
+    fn uninterruptible_timeout_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/loop.wat")?;
+        let count = plugin.function::<i32, i32>("count")?;
+        let result = count.call_timeout(1, Duration::from_secs(1));
+        assert!(matches!(result, Err(PluginError::Uninterruptible)));
+        assert_eq!(count(1)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn memory() -> Result<()> {
+        let limits = Limits::new().memory(3 * 65_536 + 100);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/grow.wat", (), Imports::new(), limits)?;
+        assert_eq!(plugin.peak_memory(), 65_536);
+        let grow = plugin.function::<i32, i32>("grow")?;
+        assert_eq!(grow(2)?, 1);
+        assert_eq!(grow(1)?, -1);
+        assert_eq!(plugin.function::<(), i32>("size")?(())?, 3);
+        assert_eq!(plugin.peak_memory(), 3 * 65_536);
+        Ok(())
+    }
+
+    #[test]
+    fn unlimited_memory() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/grow.wat")?;
+        assert_eq!(plugin.function::<i32, i32>("grow")?(9)?, 1);
+        assert_eq!(plugin.peak_memory(), 10 * 65_536);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_fail() {
+        let limits = Limits::new().memory_pages(0);
+        let plugin = Plugin::with_limits("./examples/plugins/grow.wat", (), Imports::new(), limits);
+        assert!(matches!(plugin, Err(PluginError::Load(_))));
+    }
+}
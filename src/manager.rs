@@ -0,0 +1,278 @@
+//! Plugins discovered in directories and managed by name.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+use anyhow::anyhow;
+
+use crate::{Plugin, PluginBuilder, PluginError, Result};
+
+/// File a bundle directory holds its plugin in, next to its other files.
+const BUNDLE_PLUGINS: [&str; 2] = ["plugin.wasm", "plugin.wat"];
+
+/// Plugins loaded from directories, indexed by name.
+///
+/// Every `.wasm` or `.wat` file in a directory is a plugin named after the
+/// file, without its extension. A subdirectory holding a `plugin.wasm` or
+/// `plugin.wat` is a bundle, a plugin named after the subdirectory.
+///
+/// ```
+/// use plugged::PluginManager;
+///
+/// let mut plugins = PluginManager::new();
+/// let report = plugins.load_dir("./examples/plugins");
+/// for (path, error) in &report.failed {
+///     eprintln!("{}: {error}", path.display());
+/// }
+/// let add = plugins.get("add").unwrap().function::<(i32, i32), i32>("add")?;
+/// assert_eq!(add((42, 1))?, 43);
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+pub struct PluginManager<T = ()> {
+    builder: PluginBuilder<T>,
+    plugins: BTreeMap<String, (PathBuf, Plugin<T>)>,
+}
+
+/// Outcome of loading the plugins of directories.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Names of the plugins loaded.
+    pub loaded: Vec<String>,
+    /// Files or directories which failed to load, the rest loaded regardless.
+    pub failed: Vec<(PathBuf, PluginError)>,
+}
+
+impl PluginManager {
+    pub fn new() -> Self {
+        Self::with_builder(PluginBuilder::new())
+    }
+}
+
+impl Default for PluginManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Send + 'static> PluginManager<T> {
+    /// Loads every plugin as configured by `builder`, each with a clone of
+    /// its host state.
+    pub fn with_builder(builder: PluginBuilder<T>) -> Self {
+        Self {
+            builder,
+            plugins: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the plugins found in `dir`, see [`load_dirs`](Self::load_dirs).
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> LoadReport {
+        self.load_dirs([dir])
+    }
+
+    /// Loads the plugins found in `dirs` in parallel. Plugins failing to load
+    /// or named like one already loaded are reported without affecting the
+    /// others.
+    pub fn load_dirs(&mut self, dirs: impl IntoIterator<Item = impl AsRef<Path>>) -> LoadReport {
+        let mut report = LoadReport::default();
+        let mut found = Vec::new();
+        for dir in dirs {
+            let dir = dir.as_ref();
+            match scan(dir) {
+                Ok(plugins) => found.extend(plugins),
+                Err(error) => report.failed.push((dir.to_owned(), error)),
+            }
+        }
+        let mut paths: BTreeMap<String, PathBuf> = BTreeMap::new();
+        let mut queue = Vec::new();
+        for (name, path) in found {
+            if let Some(loaded) = self.path(&name).or(paths.get(&name).map(PathBuf::as_path)) {
+                let error = anyhow!(
+                    "Plugin `{name}` is already loaded from {}",
+                    loaded.display()
+                );
+                report.failed.push((path, error.into()));
+            } else {
+                paths.insert(name.clone(), path.clone());
+                queue.push((name, path));
+            }
+        }
+        for (name, path, plugin) in self.load_all(queue) {
+            match plugin {
+                Ok(plugin) => {
+                    report.loaded.push(name.clone());
+                    self.plugins.insert(name, (path, plugin));
+                }
+                Err(error) => report.failed.push((path, error)),
+            }
+        }
+        report
+    }
+
+    /// Loads the plugins of `queue` on as many threads as there are CPUs.
+    fn load_all(&self, queue: Vec<(String, PathBuf)>) -> Vec<(String, PathBuf, Result<Plugin<T>>)> {
+        let workers = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(queue.len());
+        let queue = &Mutex::new(queue.into_iter());
+        let loaded = &Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                // Host state need not be Sync, each worker gets its own.
+                let builder = self.builder.clone();
+                scope.spawn(move || loop {
+                    let Some((name, path)) = queue.lock().unwrap().next() else {
+                        return;
+                    };
+                    let plugin = builder.clone().load(plugin_file(&path));
+                    loaded.lock().unwrap().push((name, path, plugin));
+                });
+            }
+        });
+        let mut loaded = std::mem::take(&mut *loaded.lock().unwrap());
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        loaded
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Plugin<T>> {
+        self.plugins.get(name).map(|(_, plugin)| plugin)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Plugin<T>> {
+        self.plugins.get_mut(name).map(|(_, plugin)| plugin)
+    }
+
+    /// File or bundle directory the plugin was loaded from.
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.plugins.get(name).map(|(path, _)| path.as_path())
+    }
+
+    /// Names of the loaded plugins, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.keys().map(String::as_str)
+    }
+
+    /// Removes the plugin, returning it if it was loaded.
+    pub fn unload(&mut self, name: &str) -> Option<Plugin<T>> {
+        self.plugins.remove(name).map(|(_, plugin)| plugin)
+    }
+
+    /// Loads the plugin again from where it was found, keeping the loaded
+    /// one if that fails.
+    pub fn reload(&mut self, name: &str) -> Result<()> {
+        let (path, plugin) = self
+            .plugins
+            .get_mut(name)
+            .ok_or_else(|| PluginError::UnknownPlugin(name.to_owned()))?;
+        *plugin = self.builder.clone().load(plugin_file(path))?;
+        Ok(())
+    }
+}
+
+/// Finds the plugins in `dir`, by name.
+fn scan(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut plugins = Vec::new();
+    for entry in fs::read_dir(dir).map_err(anyhow::Error::from)? {
+        let path = entry.map_err(anyhow::Error::from)?.path();
+        let plugin = if path.is_dir() {
+            BUNDLE_PLUGINS
+                .iter()
+                .any(|file| path.join(file).is_file())
+                .then(|| path.file_name())
+                .flatten()
+        } else {
+            matches!(path.extension(), Some(ext) if ext == "wasm" || ext == "wat")
+                .then(|| path.file_stem())
+                .flatten()
+        };
+        if let Some(name) = plugin {
+            plugins.push((name.to_string_lossy().into_owned(), path));
+        }
+    }
+    plugins.sort();
+    Ok(plugins)
+}
+
+/// Plugin file at `path`, which may be a bundle directory.
+fn plugin_file(path: &Path) -> PathBuf {
+    if !path.is_dir() {
+        return path.to_owned();
+    }
+    BUNDLE_PLUGINS
+        .iter()
+        .map(|file| path.join(file))
+        .find(|file| file.is_file())
+        .unwrap_or_else(|| path.join(BUNDLE_PLUGINS[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{PluginError, PluginManager, Result};
+
+    #[test]
+    fn manager() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("plugged-manager-{}", std::process::id()));
+        let (dir, other) = (root.join("plugins"), root.join("other"));
+        fs::create_dir_all(dir.join("bundle")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        let add = fs::read_to_string("./examples/plugins/add.wat").unwrap();
+        fs::write(dir.join("add.wat"), &add).unwrap();
+        fs::copy("./examples/plugins/loop.wat", dir.join("loop.wat")).unwrap();
+        fs::write(dir.join("bundle/plugin.wat"), &add).unwrap();
+        fs::write(dir.join("broken.wat"), "(module").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(other.join("add.wat"), &add).unwrap();
+
+        let mut plugins = PluginManager::new();
+        let report = plugins.load_dirs([&dir, &other, &root.join("missing")]);
+        assert_eq!(report.loaded, ["add", "bundle", "loop"]);
+        let failed: Vec<_> = report.failed.iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            failed,
+            [
+                &root.join("missing"),
+                &other.join("add.wat"),
+                &dir.join("broken.wat")
+            ]
+        );
+        assert_eq!(
+            plugins.names().collect::<Vec<_>>(),
+            ["add", "bundle", "loop"]
+        );
+
+        let call = |plugins: &PluginManager, name| {
+            plugins
+                .get(name)
+                .unwrap()
+                .function::<(i32, i32), i32>("add")?((42, 1))
+        };
+        assert_eq!(call(&plugins, "bundle")?, 43);
+        fs::write(
+            dir.join("bundle/plugin.wat"),
+            add.replace("i32.add", "i32.sub"),
+        )
+        .unwrap();
+        plugins.reload("bundle")?;
+        assert_eq!(call(&plugins, "bundle")?, 41);
+
+        fs::write(dir.join("add.wat"), "(module").unwrap();
+        assert!(matches!(plugins.reload("add"), Err(PluginError::Load(_))));
+        assert_eq!(call(&plugins, "add")?, 43);
+
+        assert!(plugins.unload("loop").is_some());
+        assert!(plugins.get("loop").is_none());
+        assert!(matches!(
+            plugins.reload("loop"),
+            Err(PluginError::UnknownPlugin(_))
+        ));
+        fs::remove_dir_all(root).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Manifests describing plugins.
+
+use anyhow::{anyhow, Context as _};
+use wasmer::Module;
+
+use crate::Result;
+
+/// Custom section the guest SDK records the manifest in.
+const MANIFEST: &str = "plugged.manifest";
+
+/// Description of a plugin recorded by the guest SDK's `#[export]` and
+/// `manifest!`, from the plugin's `Cargo.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Version requirement on the host's API, as declared by the plugin.
+    #[serde(default)]
+    pub host_api: Option<String>,
+}
+
+/// Reads the manifest of `module`, if it has one.
+pub(crate) fn parse(module: &Module) -> Result<Option<PluginManifest>> {
+    let mut sections = module.custom_sections(MANIFEST).peekable();
+    if sections.peek().is_none() {
+        return Ok(None);
+    }
+    // Every export records the manifest of its crate, and they all end up
+    // concatenated in one section.
+    let mut manifest: Option<PluginManifest> = None;
+    for section in sections {
+        for entry in serde_json::Deserializer::from_slice(&section).into_iter() {
+            let entry: PluginManifest = entry.context("Invalid plugin manifest")?;
+            manifest = Some(match manifest {
+                Some(manifest) => merge(manifest, entry)?,
+                None => entry,
+            });
+        }
+    }
+    manifest
+        .map(Some)
+        .ok_or_else(|| anyhow!("Empty plugin manifest").into())
+}
+
+/// Merges two copies of the manifest of the same crate, which only the one
+/// recorded by `manifest!` gives a host API.
+fn merge(mut manifest: PluginManifest, other: PluginManifest) -> Result<PluginManifest> {
+    let host_apis = manifest.host_api.is_some() && other.host_api.is_some();
+    if manifest.name != other.name
+        || manifest.version != other.version
+        || manifest.authors != other.authors
+        || manifest.description != other.description
+        || host_apis && manifest.host_api != other.host_api
+    {
+        return Err(anyhow!("Plugin has more than one manifest").into());
+    }
+    manifest.host_api = manifest.host_api.or(other.host_api);
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Plugin, PluginError, PluginManifest, Result};
+
+    fn plugin(manifest: &str) -> Result<Plugin> {
+        let manifest = manifest.replace('"', "\\\"");
+        Plugin::from_bytes(format!(
+            r#"(module (@custom "plugged.manifest" "{manifest}"))"#
+        ))
+    }
+
+    #[test]
+    fn manifest() -> Result<()> {
+        let add = plugin(r#"{"name":"add","version":"0.1.0","host_api":"^1"}"#)?;
+        assert_eq!(
+            add.manifest(),
+            Some(&PluginManifest {
+                name: "add".into(),
+                version: "0.1.0".into(),
+                authors: Vec::new(),
+                description: None,
+                host_api: Some("^1".into()),
+            })
+        );
+        assert_eq!(Plugin::new("./examples/plugins/add.wat")?.manifest(), None);
+
+        let copies = concat!(
+            r#"{"name":"add","version":"0.1.0"}"#,
+            r#"{"name":"add","version":"0.1.0","host_api":"^1"}"#,
+            r#"{"name":"add","version":"0.1.0"}"#,
+        );
+        let manifest = plugin(copies)?.manifest().cloned().unwrap();
+        assert_eq!(manifest.host_api.as_deref(), Some("^1"));
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_fail() {
+        assert!(matches!(
+            plugin(r#"{"name":"add"}"#),
+            Err(PluginError::Load(_))
+        ));
+        let twice = r#"{"name":"a","version":"1"}{"name":"b","version":"1"}"#;
+        assert!(matches!(plugin(twice), Err(PluginError::Load(_))));
+        let host_apis = concat!(
+            r#"{"name":"a","version":"1","host_api":"^1"}"#,
+            r#"{"name":"a","version":"1","host_api":"^2"}"#,
+        );
+        assert!(matches!(plugin(host_apis), Err(PluginError::Load(_))));
+    }
+}
@@ -0,0 +1,145 @@
+//! Bounds-checked access to a plugin's exported linear memory.
+
+use wasmer::{MemoryAccessError, MemoryView, ValueType, WasmRef};
+
+/// Name of the export every memory accessor operates on.
+pub(crate) const MEMORY: &str = "memory";
+
+pub(crate) fn missing() -> crate::PluginError {
+    wasmer::ExportError::Missing(MEMORY.into()).into()
+}
+
+pub(crate) fn read_bytes(
+    view: &MemoryView,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, MemoryAccessError> {
+    // Check first, so a bogus length from the guest can't allocate.
+    match offset.checked_add(len as u64) {
+        Some(end) if end <= view.data_size() => {}
+        _ => return Err(MemoryAccessError::HeapOutOfBounds),
+    }
+    let mut bytes = vec![0; len];
+    view.read(offset, &mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn read_str(
+    view: &MemoryView,
+    offset: u64,
+    len: usize,
+) -> Result<String, MemoryAccessError> {
+    String::from_utf8(read_bytes(view, offset, len)?).map_err(|_| MemoryAccessError::NonUtf8String)
+}
+
+pub(crate) fn read_value<V: ValueType>(
+    view: &MemoryView,
+    offset: u64,
+) -> Result<V, MemoryAccessError> {
+    WasmRef::new(view, offset).read()
+}
+
+pub(crate) fn write_value<V: ValueType>(
+    view: &MemoryView,
+    offset: u64,
+    value: V,
+) -> Result<(), MemoryAccessError> {
+    WasmRef::new(view, offset).write(value)
+}
+
+/// Expands to the memory accessors shared by [`Plugin`](crate::Plugin) and
+/// [`Context`](crate::Context). Expects a `with_memory` method in scope.
+macro_rules! memory_accessors {
+    () => {
+        /// Copies `buf.len()` bytes starting at `offset` into `buf`.
+        pub fn read(&self, offset: u64, buf: &mut [u8]) -> $crate::Result<()> {
+            self.with_memory(|view| view.read(offset, buf))
+        }
+
+        pub fn read_bytes(&self, offset: u64, len: usize) -> $crate::Result<Vec<u8>> {
+            self.with_memory(|view| $crate::memory::read_bytes(view, offset, len))
+        }
+
+        /// Reads `len` bytes at `offset` as UTF-8.
+        pub fn read_str(&self, offset: u64, len: usize) -> $crate::Result<String> {
+            self.with_memory(|view| $crate::memory::read_str(view, offset, len))
+        }
+
+        /// Reads a plain value laid out as in the guest at `offset`.
+        pub fn read_value<V: wasmer::ValueType>(&self, offset: u64) -> $crate::Result<V> {
+            self.with_memory(|view| $crate::memory::read_value(view, offset))
+        }
+
+        pub fn write(&self, offset: u64, bytes: &[u8]) -> $crate::Result<()> {
+            self.with_memory(|view| view.write(offset, bytes))
+        }
+
+        pub fn write_str(&self, offset: u64, s: &str) -> $crate::Result<()> {
+            self.write(offset, s.as_bytes())
+        }
+
+        pub fn write_value<V: wasmer::ValueType>(
+            &self,
+            offset: u64,
+            value: V,
+        ) -> $crate::Result<()> {
+            self.with_memory(|view| $crate::memory::write_value(view, offset, value))
+        }
+    };
+}
+
+pub(crate) use memory_accessors;
+
+#[cfg(test)]
+mod tests {
+    use crate::{Plugin, PluginError, Result};
+    use wasmer::MemoryAccessError;
+
+    #[test]
+    fn read_write() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/memory.wat")?;
+        assert_eq!(plugin.read_str(0, 5)?, "hello");
+
+        plugin.write_value(16, 0x2a_u32)?;
+        assert_eq!(plugin.read_value::<u32>(16)?, 42);
+        let load = plugin.function::<i32, i32>("load")?;
+        assert_eq!(load(16)?, 42);
+
+        plugin.write_str(0, "jelly")?;
+        assert_eq!(plugin.read_bytes(0, 5)?, b"jelly");
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_bounds_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/memory.wat")?;
+        let result = plugin.read_bytes(65534, 4);
+        assert!(matches!(
+            result,
+            Err(PluginError::Memory(MemoryAccessError::HeapOutOfBounds))
+        ));
+        let result = plugin.read_bytes(0, usize::MAX);
+        assert!(matches!(
+            result,
+            Err(PluginError::Memory(MemoryAccessError::HeapOutOfBounds))
+        ));
+
+        plugin.write(0, &[0xff])?;
+        let result = plugin.read_str(0, 1);
+        assert!(matches!(
+            result,
+            Err(PluginError::Memory(MemoryAccessError::NonUtf8String))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_memory_fail() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/add.wat")?;
+        assert!(matches!(
+            plugin.read_bytes(0, 1),
+            Err(PluginError::Export(_))
+        ));
+        Ok(())
+    }
+}
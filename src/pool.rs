@@ -0,0 +1,394 @@
+//! Pools of instances of a plugin compiled once.
+
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use wasmer::{Engine, Module, Store};
+
+use crate::{
+    limits::Fuel, tunables::MemoryUsage, Params, Plugin, PluginBuilder, PluginError, Result,
+    Results,
+};
+
+/// Sizing of a [`PluginPool`].
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    size: usize,
+    min_idle: usize,
+    idle_timeout: Option<Duration>,
+    wait_timeout: Option<Duration>,
+}
+
+impl PoolConfig {
+    /// Keeps up to `size` instances, all instantiated up front.
+    pub fn new(size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            min_idle: 0,
+            idle_timeout: None,
+            wait_timeout: None,
+        }
+    }
+
+    /// Drops instances left unused for `timeout`, down to
+    /// [`min_idle`](Self::min_idle). They are instantiated again on demand.
+    ///
+    /// A background thread checks for them every `timeout`, so they go even
+    /// if the pool isn't used anymore.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Instances kept through [`idle_timeout`](Self::idle_timeout), 0 by
+    /// default.
+    pub fn min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Fails a checkout with [`PluginError::PoolTimeout`] after waiting
+    /// `timeout` for an instance, instead of waiting indefinitely.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = Some(timeout);
+        self
+    }
+}
+
+/// Instances of a plugin checked out one call at a time, created with
+/// [`PluginBuilder::pool`].
+///
+/// The plugin is compiled once and each instance gets a clone of the host
+/// state. Returned instances get their host state and their
+/// [`Limits::fuel`](crate::Limits::fuel) budget back and lose the output
+/// captured from WASI, while the guest's own
+/// memory carries over to the next checkout. Instances in which a call
+/// trapped, by panicking, running out of fuel or timing out, are dropped
+/// instead of going back to the pool. Instances share one memory usage
+/// counter, so [`Plugin::peak_memory`] covers the whole pool.
+///
+/// ```
+/// use plugged::{PluginBuilder, PoolConfig};
+///
+/// let pool = PluginBuilder::new().pool("./examples/plugins/add.wat", PoolConfig::new(4))?;
+/// let sum: i32 = pool.call("add", (42, 1))?;
+/// assert_eq!(sum, 43);
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+pub struct PluginPool<T = ()> {
+    builder: PluginBuilder<T>,
+    engine: Engine,
+    module: Module,
+    memory_usage: Arc<MemoryUsage>,
+    config: PoolConfig,
+    /// Shared with the thread evicting idle instances, which stops once the
+    /// pool is dropped.
+    state: Arc<Mutex<State<T>>>,
+    returned: Condvar,
+}
+
+struct State<T> {
+    /// Instances waiting for a call, the most recently used last.
+    idle: VecDeque<(Plugin<T>, Instant)>,
+    /// Instances alive or being instantiated, idle or not.
+    size: usize,
+}
+
+impl<T> State<T> {
+    /// Drops instances idle for longer than `timeout`, down to `min_idle`.
+    fn evict(&mut self, timeout: Duration, min_idle: usize) {
+        while self.idle.len() > min_idle {
+            match self.idle.front() {
+                Some((_, since)) if since.elapsed() >= timeout => {
+                    self.idle.pop_front();
+                    self.size -= 1;
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> PluginPool<T> {
+    pub(crate) fn new(
+        builder: PluginBuilder<T>,
+        engine: Engine,
+        module: Module,
+        memory_usage: Arc<MemoryUsage>,
+        config: PoolConfig,
+    ) -> Result<Self> {
+        let pool = Self {
+            builder,
+            engine,
+            module,
+            memory_usage,
+            state: Arc::new(Mutex::new(State {
+                idle: VecDeque::with_capacity(config.size),
+                size: 0,
+            })),
+            config,
+            returned: Condvar::new(),
+        };
+        for _ in 0..pool.config.size {
+            let plugin = pool.instantiate()?;
+            let mut state = pool.lock();
+            state.idle.push_back((plugin, Instant::now()));
+            state.size += 1;
+        }
+        if let Some(timeout) = pool.config.idle_timeout {
+            let state = Arc::downgrade(&pool.state);
+            let min_idle = pool.config.min_idle;
+            thread::spawn(move || loop {
+                thread::sleep(timeout);
+                let Some(state) = state.upgrade() else {
+                    break;
+                };
+                let mut state = state.lock().unwrap();
+                state.evict(timeout, min_idle);
+            });
+        }
+        Ok(pool)
+    }
+
+    /// Checks out an instance, waiting for one to be returned if all of them
+    /// are in use.
+    pub fn get(&self) -> Result<PooledPlugin<'_, T>> {
+        let start = Instant::now();
+        let mut state = self.lock();
+        loop {
+            if let Some(timeout) = self.config.idle_timeout {
+                state.evict(timeout, self.config.min_idle);
+            }
+            if let Some((plugin, _)) = state.idle.pop_back() {
+                return Ok(self.checkout(plugin));
+            }
+            if state.size < self.config.size {
+                state.size += 1;
+                drop(state);
+                return match self.instantiate() {
+                    Ok(plugin) => Ok(self.checkout(plugin)),
+                    Err(error) => {
+                        self.lock().size -= 1;
+                        self.returned.notify_one();
+                        Err(error)
+                    }
+                };
+            }
+            state = match self.config.wait_timeout {
+                Some(timeout) => {
+                    let waited = start.elapsed();
+                    if waited >= timeout {
+                        return Err(PluginError::PoolTimeout { waited });
+                    }
+                    let (state, _) = self.returned.wait_timeout(state, timeout - waited).unwrap();
+                    state
+                }
+                None => self.returned.wait(state).unwrap(),
+            };
+        }
+    }
+
+    /// Calls `name` on an instance checked out for the call.
+    pub fn call<Args, Rets>(&self, name: impl AsRef<str>, args: Args) -> Result<Rets>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let plugin = self.get()?;
+        let f = plugin.function::<Args, Rets>(name)?;
+        f(args)
+    }
+
+    /// Number of instances alive, checked out or idle.
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    /// Number of instances waiting for a call.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    fn instantiate(&self) -> Result<Plugin<T>> {
+        let store = Store::new(self.engine.clone());
+        let module = self.module.clone();
+        let memory_usage = self.memory_usage.clone();
+        self.builder
+            .clone()
+            .instantiate(store, module, memory_usage)
+    }
+
+    fn checkout(&self, plugin: Plugin<T>) -> PooledPlugin<'_, T> {
+        PooledPlugin {
+            pool: self,
+            plugin: Some(plugin),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// An instance checked out of a [`PluginPool`], returned to it on drop.
+pub struct PooledPlugin<'pool, T: Clone + Send + 'static = ()> {
+    pool: &'pool PluginPool<T>,
+    plugin: Option<Plugin<T>>,
+}
+
+impl<T: Clone + Send + 'static> Deref for PooledPlugin<'_, T> {
+    type Target = Plugin<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.plugin.as_ref().unwrap()
+    }
+}
+
+impl<T: Clone + Send + 'static> PooledPlugin<'_, T> {
+    /// Restores the host state and fuel the instance was created with and
+    /// drops the output it captured, telling whether it succeeded.
+    fn reset(&self, plugin: &Plugin<T>) -> bool {
+        let Ok(mut data) = plugin.data_mut() else {
+            return false;
+        };
+        *data = self.pool.builder.data.clone();
+        drop(data);
+        #[cfg(feature = "wasi")]
+        {
+            plugin.take_stdout();
+            plugin.take_stderr();
+        }
+        match plugin.limits.fuel {
+            Some(Fuel::Plugin(fuel)) => plugin.set_fuel(fuel).is_ok(),
+            _ => true,
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> Drop for PooledPlugin<'_, T> {
+    fn drop(&mut self) {
+        let plugin = self.plugin.take().unwrap();
+        let reusable = !plugin.poisoned.get() && !plugin.trapped.get() && self.reset(&plugin);
+        let mut state = self.pool.lock();
+        if reusable {
+            state.idle.push_back((plugin, Instant::now()));
+        } else {
+            state.size -= 1;
+        }
+        drop(state);
+        self.pool.returned.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{Limits, PluginBuilder, PluginError, PoolConfig, Result};
+
+    #[test]
+    fn pool() -> Result<()> {
+        let pool = PluginBuilder::new().pool("./examples/plugins/add.wat", PoolConfig::new(2))?;
+        assert_eq!((pool.size(), pool.idle()), (2, 2));
+        thread::scope(|scope| {
+            for i in 0..8 {
+                let pool = &pool;
+                scope.spawn(move || assert_eq!(pool.call::<_, i32>("add", (i, 1)).unwrap(), i + 1));
+            }
+        });
+        assert_eq!((pool.size(), pool.idle()), (2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn state() -> Result<()> {
+        let pool = PluginBuilder::with_state(vec![1])
+            .pool("./examples/plugins/add.wat", PoolConfig::new(2))?;
+        let first = pool.get()?;
+        first.data_mut()?.push(2);
+        let second = pool.get()?;
+        assert_eq!(*second.data()?, [1]);
+        drop(second);
+        drop(first);
+        assert_eq!(*pool.get()?.data()?, [1]);
+        Ok(())
+    }
+
+    #[cfg(feature = "wasi")]
+    #[test]
+    fn wasi_output() -> Result<()> {
+        let imports = crate::Imports::new().wasi(crate::Wasi::new().capture_stdout());
+        let pool = PluginBuilder::new()
+            .imports(imports)
+            .pool("./examples/plugins/wasi.wat", PoolConfig::new(1))?;
+        assert_eq!(pool.call::<(), i32>("hello", ())?, 0);
+        assert!(pool.get()?.take_stdout().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn idle_timeout() -> Result<()> {
+        let config = PoolConfig::new(3)
+            .idle_timeout(Duration::from_millis(10))
+            .min_idle(1);
+        let pool = PluginBuilder::new().pool("./examples/plugins/add.wat", config)?;
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!((pool.size(), pool.idle()), (1, 1));
+        let plugin = pool.get()?;
+        assert_eq!((pool.size(), pool.idle()), (1, 0));
+        let _other = pool.get()?;
+        assert_eq!(pool.size(), 2);
+        drop(plugin);
+        Ok(())
+    }
+
+    #[test]
+    fn wait_timeout_fail() -> Result<()> {
+        let config = PoolConfig::new(1).wait_timeout(Duration::from_millis(10));
+        let pool = PluginBuilder::new().pool("./examples/plugins/add.wat", config)?;
+        let _plugin = pool.get()?;
+        assert!(matches!(pool.get(), Err(PluginError::PoolTimeout { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn fuel() -> Result<()> {
+        let pool = PluginBuilder::new()
+            .limits(Limits::new().fuel(1_000))
+            .pool("./examples/plugins/loop.wat", PoolConfig::new(1))?;
+        assert_eq!(pool.call::<i32, i32>("count", 10)?, 10);
+        assert_eq!(pool.get()?.fuel()?, Some(1_000));
+
+        let result = pool.call::<(), ()>("spin", ());
+        assert!(matches!(result, Err(PluginError::OutOfFuel)));
+        assert_eq!(pool.size(), 0);
+        assert_eq!(pool.call::<i32, i32>("count", 10)?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn panicked() -> Result<()> {
+        let pool = PluginBuilder::new().pool("./examples/plugins/panic.wat", PoolConfig::new(1))?;
+        let result = pool.call::<(), ()>("fail", ());
+        assert!(matches!(result, Err(PluginError::Panic(_))));
+        assert_eq!((pool.size(), pool.idle()), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn poisoned() -> Result<()> {
+        let pool = PluginBuilder::new()
+            .limits(Limits::new().timeout(Duration::from_millis(10)))
+            .pool("./examples/plugins/loop.wat", PoolConfig::new(1))?;
+        let result = pool.call::<(), ()>("spin", ());
+        assert!(matches!(result, Err(PluginError::Timeout { .. })));
+        assert_eq!(pool.size(), 0);
+        assert_eq!(pool.call::<i32, i32>("count", 3)?, 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Plugins reloaded when their file changes.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{Params, Plugin, PluginBuilder, PluginError, Result, Results};
+
+/// Outcome of reloading a [`WatchedPlugin`].
+#[derive(Clone, Debug)]
+pub enum Reload {
+    /// The new version replaced the old one.
+    Loaded,
+    /// The new version failed to load, the old one keeps running.
+    Failed(Arc<PluginError>),
+}
+
+/// A plugin loaded from a file and reloaded in the background whenever the
+/// file changes, created with [`PluginBuilder::watch`].
+///
+/// A reloaded instance replaces the old one between calls, starting over with
+/// a clone of the builder's host state. Function handles are tied to the
+/// instance they came from, so calls look up the function every time.
+///
+/// ```
+/// use plugged::Plugin;
+///
+/// let plugin = Plugin::watch("./examples/plugins/add.wat")?;
+/// let reloads = plugin.subscribe();
+/// assert_eq!(plugin.call::<(i32, i32), i32>("add", (42, 1))?, 43);
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+pub struct WatchedPlugin<T = ()> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    /// Behind a mutex only to be shared with the watching thread.
+    builder: Mutex<PluginBuilder<T>>,
+    path: PathBuf,
+    plugin: Mutex<Plugin<T>>,
+    /// Modification time and length of the file as last loaded, locked
+    /// throughout a reload.
+    version: Mutex<Option<(SystemTime, u64)>>,
+    subscribers: Mutex<Vec<mpsc::Sender<Reload>>>,
+}
+
+impl<T: Clone + Send + 'static> WatchedPlugin<T> {
+    pub(crate) fn new(
+        builder: PluginBuilder<T>,
+        path: PathBuf,
+        interval: Duration,
+    ) -> Result<Self> {
+        let version = version(&path);
+        let plugin = builder.clone().load(&path)?;
+        let inner = Arc::new(Inner {
+            builder: Mutex::new(builder),
+            path,
+            plugin: Mutex::new(plugin),
+            version: Mutex::new(version),
+            subscribers: Mutex::default(),
+        });
+        let watched = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("plugged-reload".into())
+            .spawn(move || watch(watched, interval))
+            .map_err(anyhow::Error::from)?;
+        Ok(Self { inner })
+    }
+
+    /// Borrows the current instance, holding off reloads until the guard is
+    /// dropped.
+    pub fn lock(&self) -> MutexGuard<'_, Plugin<T>> {
+        self.inner.lock()
+    }
+
+    /// Calls `name` on the current instance.
+    pub fn call<Args, Rets>(&self, name: impl AsRef<str>, args: Args) -> Result<Rets>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let plugin = self.lock();
+        let f = plugin.function::<Args, Rets>(name)?;
+        f(args)
+    }
+
+    /// Reloads the plugin now, whether its file changed or not.
+    pub fn reload(&self) -> Reload {
+        let mut version = self.inner.version.lock().unwrap();
+        *version = self::version(&self.inner.path);
+        self.inner.reload()
+    }
+
+    /// Returns a channel receiving the outcome of every later reload.
+    pub fn subscribe(&self) -> mpsc::Receiver<Reload> {
+        let (sender, receiver) = mpsc::channel();
+        self.inner.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl<T: Clone + Send + 'static> Inner<T> {
+    fn lock(&self) -> MutexGuard<'_, Plugin<T>> {
+        self.plugin.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Loads the plugin again and swaps it in, or keeps the old one on
+    /// failure. Subscribers hear about both.
+    fn reload(&self) -> Reload {
+        let builder = self.builder.lock().unwrap().clone();
+        let reload = match builder.load(&self.path) {
+            Ok(plugin) => {
+                *self.lock() = plugin;
+                Reload::Loaded
+            }
+            Err(error) => Reload::Failed(Arc::new(error)),
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(reload.clone()).is_ok());
+        reload
+    }
+}
+
+/// Polls the file every `interval` until the plugin is dropped.
+fn watch<T: Clone + Send + 'static>(inner: Weak<Inner<T>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let mut version = inner.version.lock().unwrap();
+        let current = self::version(&inner.path);
+        // A missing file is most likely being rewritten.
+        if current.is_some() && current != *version {
+            *version = current;
+            inner.reload();
+        }
+    }
+}
+
+fn version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use crate::{Plugin, PluginError, Reload, Result};
+
+    #[test]
+    fn reload() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("plugged-reload-{}.wat", std::process::id()));
+        let add = fs::read_to_string("./examples/plugins/add.wat").unwrap();
+        fs::write(&path, &add).unwrap();
+        let plugin = Plugin::builder().watch(&path, Duration::from_millis(10))?;
+        let reloads = plugin.subscribe();
+        assert_eq!(plugin.call::<(i32, i32), i32>("add", (42, 1))?, 43);
+
+        fs::write(&path, add.replace("i32.add", "i32.sub ;; changed")).unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(reload, Reload::Loaded));
+        assert_eq!(plugin.call::<(i32, i32), i32>("add", (42, 1))?, 41);
+
+        fs::write(&path, "(module").unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(reload, Reload::Failed(_)));
+        assert_eq!(plugin.call::<(i32, i32), i32>("add", (42, 1))?, 41);
+        let Reload::Failed(error) = plugin.reload() else {
+            panic!("expected a failed reload");
+        };
+        assert!(matches!(*error, PluginError::Load(_)));
+        assert!(matches!(reloads.try_recv(), Ok(Reload::Failed(_))));
+
+        fs::write(&path, &add).unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(reload, Reload::Loaded));
+        assert_eq!(plugin.call::<(i32, i32), i32>("add", (42, 1))?, 43);
+        fs::remove_file(path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! The tokio runtime used by WASI and async host functions.
+
+use std::sync::OnceLock;
+#[cfg(feature = "async")]
+use std::{cell::Cell, future::Future};
+
+use tokio::runtime::{Handle, Runtime};
+#[cfg(feature = "async")]
+use tokio::{runtime::RuntimeFlavor, task::JoinHandle};
+
+#[cfg(feature = "async")]
+use crate::{PluginError, Result};
+
+/// Returns the caller's tokio runtime, or a runtime shared by all plugins
+/// outside of one.
+pub(crate) fn handle() -> Handle {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    Handle::try_current().unwrap_or_else(|_| {
+        RUNTIME
+            .get_or_init(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .expect("failed to start the tokio runtime")
+            })
+            .handle()
+            .clone()
+    })
+}
+
+#[cfg(feature = "async")]
+thread_local! {
+    /// Whether this thread runs a call for `call_async`, on tokio's blocking
+    /// pool.
+    static ASYNC_CALL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs the plugin call `call` on tokio's blocking pool, where the futures of
+/// async host functions are awaited on the caller's runtime.
+#[cfg(feature = "async")]
+pub(crate) fn spawn_call<R>(call: impl FnOnce() -> R + Send + 'static) -> JoinHandle<R>
+where
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let _call = AsyncCall::enter();
+        call()
+    })
+}
+
+/// Waits for the future of an async host function called by the guest,
+/// blocking the guest's thread until it completes.
+///
+/// During a call from [`spawn_call`], the future is spawned on the caller's
+/// runtime. Otherwise it is run on the calling thread, after moving the
+/// other tasks of a multi-threaded runtime's worker to another thread.
+/// Blocking a current-thread runtime would stall it, so that fails.
+#[cfg(feature = "async")]
+pub(crate) fn wait<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    if ASYNC_CALL.get() {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        Handle::current().spawn(async move {
+            let _ = sender.send(future.await);
+        });
+        // The runtime shut down before the future completed.
+        return receiver.recv().map_err(|_| PluginError::Cancelled);
+    }
+    match Handle::try_current() {
+        Err(_) => Ok(handle().block_on(future)),
+        Ok(handle) => match handle.runtime_flavor() {
+            RuntimeFlavor::MultiThread => {
+                Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+            }
+            _ => Err(PluginError::CurrentThreadRuntime),
+        },
+    }
+}
+
+/// Marks the current thread as running a call for [`spawn_call`] until
+/// dropped, even by a panic.
+#[cfg(feature = "async")]
+struct AsyncCall;
+
+#[cfg(feature = "async")]
+impl AsyncCall {
+    fn enter() -> Self {
+        ASYNC_CALL.set(true);
+        Self
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for AsyncCall {
+    fn drop(&mut self) {
+        ASYNC_CALL.set(false);
+    }
+}
@@ -0,0 +1,327 @@
+//! Plugins shared between threads.
+
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+    time::Duration,
+};
+
+#[cfg(feature = "async")]
+use std::future::Future;
+
+use crate::{deadline::Cancel, Params, Plugin, PluginError, Result, Results};
+
+/// A [`Plugin`] behind a mutex, which can be cloned and sent to other
+/// threads.
+///
+/// Calls from different threads take turns on the plugin's single instance.
+///
+/// ```
+/// use plugged::Plugin;
+///
+/// let plugin = Plugin::new("./examples/plugins/add.wat")?.into_shared();
+/// let add = plugin.function::<(i32, i32), i32>("add")?;
+/// let sum = std::thread::spawn(move || add.call((42, 1))).join().unwrap()?;
+/// assert_eq!(sum, 43);
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+pub struct SharedPlugin<T = ()> {
+    plugin: Arc<Mutex<Plugin<T>>>,
+    /// Thread running a call, which would deadlock locking the plugin again.
+    caller: Arc<Mutex<Option<ThreadId>>>,
+}
+
+impl<T> Clone for SharedPlugin<T> {
+    fn clone(&self) -> Self {
+        Self {
+            plugin: self.plugin.clone(),
+            caller: self.caller.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> SharedPlugin<T> {
+    pub fn new(plugin: Plugin<T>) -> Self {
+        Self {
+            plugin: Arc::new(Mutex::new(plugin)),
+            caller: Arc::default(),
+        }
+    }
+
+    /// Waits for other threads' calls to finish and borrows the plugin,
+    /// e.g. to reach its host state or memory.
+    ///
+    /// Host functions must use their [`Context`](crate::Context) instead,
+    /// locking the plugin during one of its calls deadlocks.
+    pub fn lock(&self) -> MutexGuard<'_, Plugin<T>> {
+        self.plugin.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Like [`Plugin::function`], returning a handle which owns a reference
+    /// to the plugin and can be moved to other threads.
+    pub fn function<Args, Rets>(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<SharedFunction<Args, Rets, T>>
+    where
+        Args: Params,
+        Rets: Results,
+    {
+        let function = self.lock().typed_export::<Args, Rets>(name.as_ref())?;
+        Ok(SharedFunction {
+            plugin: self.clone(),
+            function,
+            types: PhantomData,
+        })
+    }
+}
+
+/// Exported function of a [`SharedPlugin`].
+pub struct SharedFunction<Args = (), Rets = (), T = ()> {
+    plugin: SharedPlugin<T>,
+    function: wasmer::Function,
+    types: PhantomData<fn(Args) -> Rets>,
+}
+
+impl<Args, Rets, T> Clone for SharedFunction<Args, Rets, T> {
+    fn clone(&self) -> Self {
+        Self {
+            plugin: self.plugin.clone(),
+            function: self.function.clone(),
+            types: PhantomData,
+        }
+    }
+}
+
+impl<Args, Rets, T> SharedFunction<Args, Rets, T>
+where
+    Args: Params,
+    Rets: Results,
+    T: Send + 'static,
+{
+    pub fn call(&self, args: Args) -> Result<Rets> {
+        self.invoke(args, None, None)
+    }
+
+    /// See [`Function::call_timeout`](crate::Function::call_timeout).
+    pub fn call_timeout(&self, args: Args, timeout: Duration) -> Result<Rets> {
+        self.invoke(args, Some(timeout), None)
+    }
+
+    fn invoke(
+        &self,
+        args: Args,
+        timeout: Option<Duration>,
+        cancel: Option<&Arc<Cancel>>,
+    ) -> Result<Rets> {
+        let shared = &self.plugin;
+        let thread = thread::current().id();
+        if *shared.caller.lock().unwrap() == Some(thread) {
+            return Err(PluginError::Reentrant);
+        }
+        // A thread panicked mid-call, leaving the guest in an unknown state.
+        let plugin = shared.plugin.lock().map_err(|_| PluginError::Poisoned)?;
+        let _caller = Caller::enter(&shared.caller, thread);
+        plugin.invoke(&self.function, args, timeout, cancel)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<Args, Rets, T> SharedFunction<Args, Rets, T>
+where
+    Args: Params + Send + 'static,
+    Rets: Results + Send + 'static,
+    T: Send + 'static,
+{
+    /// Calls the function on tokio's blocking thread pool, so the guest
+    /// doesn't hold up the async executor, and awaits the futures of async
+    /// host functions on the caller's runtime. Must be polled within a tokio
+    /// runtime.
+    ///
+    /// Dropping the future before it completes cancels the call. A call that
+    /// hasn't started yet is skipped. A running one is interrupted like on a
+    /// timeout if the plugin was loaded with [`Limits::fuel`] or
+    /// [`Limits::timeout`], which poisons it, and otherwise finishes in the
+    /// background.
+    ///
+    /// [`Limits::fuel`]: crate::Limits::fuel
+    /// [`Limits::timeout`]: crate::Limits::timeout
+    pub fn call_async(&self, args: Args) -> impl Future<Output = Result<Rets>> + Send + 'static {
+        self.spawn(args, None)
+    }
+
+    /// Like [`call_async`](Self::call_async), failing with
+    /// [`PluginError::Timeout`] if the call runs longer than `timeout`.
+    pub fn call_async_timeout(
+        &self,
+        args: Args,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Rets>> + Send + 'static {
+        self.spawn(args, Some(timeout))
+    }
+
+    fn spawn(
+        &self,
+        args: Args,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Rets>> + Send + 'static {
+        let function = self.clone();
+        let cancel = Arc::new(Cancel::default());
+        let on_drop = CancelOnDrop(cancel.clone());
+        let task =
+            crate::runtime::spawn_call(move || function.invoke(args, timeout, Some(&cancel)));
+        async move {
+            let _on_drop = on_drop;
+            match task.await {
+                Ok(result) => result,
+                Err(error) => match error.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    // The runtime shut down before the call could start.
+                    Err(_) => Err(PluginError::Cancelled),
+                },
+            }
+        }
+    }
+}
+
+/// Cancels a call once the future awaiting it is dropped.
+#[cfg(feature = "async")]
+struct CancelOnDrop(Arc<Cancel>);
+
+#[cfg(feature = "async")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Marks a thread as running a call until dropped, even by a panic.
+struct Caller<'a>(&'a Mutex<Option<ThreadId>>);
+
+impl<'a> Caller<'a> {
+    fn enter(caller: &'a Mutex<Option<ThreadId>>, thread: ThreadId) -> Self {
+        *caller.lock().unwrap() = Some(thread);
+        Self(caller)
+    }
+}
+
+impl Drop for Caller<'_> {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::OnceLock, thread};
+
+    use crate::{Context, Imports, Plugin, PluginError, Result, SharedFunction, SharedPlugin};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<SharedPlugin<u32>>();
+        assert_send_sync::<SharedFunction<(i32, i32), i32, u32>>();
+    }
+
+    #[test]
+    fn reentrant_fail() -> Result<()> {
+        static INNER: OnceLock<SharedFunction<i32, i32>> = OnceLock::new();
+        let imports = Imports::new().function("env", "callback", |x: i32| {
+            let result = INNER.get().unwrap().call(x);
+            assert!(matches!(result, Err(PluginError::Reentrant)));
+            x
+        });
+        let plugin =
+            Plugin::with_imports("./examples/plugins/reentrant.wat", imports)?.into_shared();
+        let _ = INNER.set(plugin.function("inner")?);
+        assert_eq!(plugin.function::<i32, i32>("outer")?.call(1)?, 1);
+        assert_eq!(INNER.get().unwrap().call(1)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn threads() -> Result<()> {
+        let imports = Imports::new().function("env", "double", |mut ctx: Context<u32>, x: i32| {
+            *ctx.data_mut() += 1;
+            x * 2
+        });
+        let plugin = Plugin::with_state("./examples/plugins/host.wat", 0, imports)?.into_shared();
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let quadruple = quadruple.clone();
+                thread::spawn(move || quadruple.call(i))
+            })
+            .collect();
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap()?, i as i32 * 4);
+        }
+        assert_eq!(*plugin.lock().data()?, 8);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn call_async() -> Result<()> {
+        let plugin = Plugin::new("./examples/plugins/add.wat")?.into_shared();
+        let add = plugin.function::<(i32, i32), i32>("add")?;
+        assert_eq!(add.call_async((42, 1)).await?, 43);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn drop_call_async() -> Result<()> {
+        use std::time::Duration;
+
+        let imports = Imports::new().function("env", "double", |mut ctx: Context<u32>, x: i32| {
+            thread::sleep(Duration::from_millis(20));
+            *ctx.data_mut() += 1;
+            x * 2
+        });
+        let plugin = Plugin::with_state("./examples/plugins/host.wat", 0, imports)?.into_shared();
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        let result = tokio::time::timeout(Duration::from_millis(10), quadruple.call_async(3)).await;
+        assert!(result.is_err());
+        assert_eq!(quadruple.call_async(3).await?, 12);
+        assert_eq!(*plugin.lock().data()?, 4);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancel() -> Result<()> {
+        use std::time::Duration;
+
+        let limits = crate::Limits::new().fuel(u64::MAX);
+        let plugin =
+            Plugin::with_limits("./examples/plugins/loop.wat", (), Imports::new(), limits)?
+                .into_shared();
+        let spin = plugin.function::<(), ()>("spin")?;
+        let count = plugin.function::<i32, i32>("count")?;
+        let result = tokio::time::timeout(Duration::from_millis(10), spin.call_async(())).await;
+        assert!(result.is_err());
+        assert!(matches!(
+            count.call_async(1).await,
+            Err(PluginError::Poisoned)
+        ));
+        assert!(plugin.lock().is_poisoned());
+
+        let imports = Imports::new().function("env", "double", |mut ctx: Context<u32>, x: i32| {
+            *ctx.data_mut() += 1;
+            x * 2
+        });
+        let plugin = Plugin::with_state("./examples/plugins/host.wat", 0, imports)?.into_shared();
+        let quadruple = plugin.function::<i32, i32>("quadruple")?;
+        let guard = plugin.lock();
+        drop(quadruple.call_async(3));
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(quadruple.call_async(3).await?, 12);
+        assert_eq!(*plugin.lock().data()?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! Engine tunables capping the linear memory of a plugin.
+//!
+//! Memories are created with their maximum lowered to the limit, so growing
+//! past it fails like any other `memory.grow` and returns -1 to the guest.
+//! Every memory is wrapped to keep track of the plugin's peak memory usage.
+
+use std::{
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use wasmer::{BaseTunables, MemoryError, MemoryType, Pages, TableType};
+use wasmer_vm::{
+    LinearMemory, MemoryStyle, NotifyLocation, TableStyle, Trap, VMConfig, VMMemory,
+    VMMemoryDefinition, VMTable, VMTableDefinition, WaiterError,
+};
+
+/// Memory currently held by a plugin and the most it ever held, in bytes.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryUsage {
+    current: AtomicU64,
+    peak: AtomicU64,
+}
+
+impl MemoryUsage {
+    pub(crate) fn peak(&self) -> u64 {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    fn add(&self, pages: Pages) {
+        let bytes = pages.bytes().0 as u64;
+        let current = self.current.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.peak.fetch_max(current, Ordering::SeqCst);
+    }
+
+    fn remove(&self, pages: Pages) {
+        let bytes = pages.bytes().0 as u64;
+        self.current.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct Tunables {
+    base: BaseTunables,
+    max_memory: Option<Pages>,
+    usage: Arc<MemoryUsage>,
+}
+
+impl Tunables {
+    pub(crate) fn new(base: BaseTunables, max_memory: Option<Pages>) -> Self {
+        Self {
+            base,
+            max_memory,
+            usage: Arc::default(),
+        }
+    }
+
+    pub(crate) fn usage(&self) -> Arc<MemoryUsage> {
+        self.usage.clone()
+    }
+
+    /// Lowers the maximum of `ty` to the limit.
+    fn limit(&self, ty: &MemoryType) -> MemoryType {
+        let Some(max_memory) = self.max_memory else {
+            return *ty;
+        };
+        let maximum = ty
+            .maximum
+            .map_or(max_memory, |maximum| maximum.min(max_memory));
+        MemoryType {
+            maximum: Some(maximum),
+            ..*ty
+        }
+    }
+
+    /// Fails if `ty` needs more memory than the limit to start with.
+    fn check(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        match self.max_memory {
+            Some(max_memory) if ty.minimum > max_memory => {
+                Err(MemoryError::MinimumMemoryTooLarge {
+                    min_requested: ty.minimum,
+                    max_allowed: max_memory,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn track(&self, memory: VMMemory) -> VMMemory {
+        self.usage.add(memory.size());
+        VMMemory(Box::new(Tracked {
+            memory,
+            usage: self.usage.clone(),
+        }))
+    }
+}
+
+impl wasmer::Tunables for Tunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.limit(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.check(ty)?;
+        let memory = self.base.create_host_memory(&self.limit(ty), style)?;
+        Ok(self.track(memory))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        self.check(ty)?;
+        let memory = self
+            .base
+            .create_vm_memory(&self.limit(ty), style, vm_definition_location)?;
+        Ok(self.track(memory))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+
+    fn vmconfig(&self) -> &VMConfig {
+        self.base.vmconfig()
+    }
+}
+
+/// A memory reporting its growth to the plugin's [`MemoryUsage`].
+#[derive(Debug)]
+struct Tracked {
+    memory: VMMemory,
+    usage: Arc<MemoryUsage>,
+}
+
+impl LinearMemory for Tracked {
+    fn ty(&self) -> MemoryType {
+        self.memory.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.memory.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.memory.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let previous = self.memory.grow(delta)?;
+        self.usage.add(delta);
+        Ok(previous)
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.memory.vmmemory()
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        self.memory.try_clone()
+    }
+
+    unsafe fn initialize_with_data(&self, start: usize, data: &[u8]) -> Result<(), Trap> {
+        self.memory.initialize_with_data(start, data)
+    }
+
+    fn copy(&mut self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        self.memory.copy()
+    }
+
+    fn do_wait(
+        &mut self,
+        dst: NotifyLocation,
+        timeout: Option<Duration>,
+    ) -> Result<u32, WaiterError> {
+        self.memory.do_wait(dst, timeout)
+    }
+
+    fn do_notify(&mut self, dst: NotifyLocation, count: u32) -> u32 {
+        self.memory.do_notify(dst, count)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.usage.remove(self.memory.size());
+    }
+}
@@ -0,0 +1,196 @@
+//! WASI support for plugins compiled to `wasm32-wasi`, backed by
+//! `wasmer-wasix`.
+
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::runtime::{EnterGuard, Handle};
+use virtual_fs::Pipe;
+use wasmer::{Instance, Module, Store};
+use wasmer_wasix::{
+    runtime::task_manager::tokio::TokioTaskManager, PluggableRuntime, WasiEnv, WasiFunctionEnv,
+};
+
+/// WASI configuration of a plugin, attached with [`Imports::wasi`](crate::Imports::wasi).
+///
+/// By default the plugin gets no arguments, no environment variables, no
+/// directories, and writes to the host's stdout and stderr.
+///
+/// ```
+/// use plugged::{Imports, Plugin, Wasi};
+///
+/// let wasi = Wasi::new().arg("--verbose").env("LANG", "C").capture_stdout();
+/// let plugin = Plugin::with_imports("./examples/plugins/wasi.wat", Imports::new().wasi(wasi))?;
+/// # Ok::<(), plugged::PluginError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Wasi {
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    dirs: Vec<(String, PathBuf)>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+}
+
+impl Wasi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a command line argument after the program name.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn envs<I, K, V>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.envs
+            .extend(envs.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Gives the plugin read-write access to the host directory `dir` under
+    /// the same path.
+    pub fn preopen_dir(self, dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let guest = dir.to_string_lossy().into_owned();
+        self.map_dir(guest, dir)
+    }
+
+    /// Gives the plugin read-write access to the host directory `dir` as
+    /// `guest`.
+    pub fn map_dir(mut self, guest: impl Into<String>, dir: impl Into<PathBuf>) -> Self {
+        self.dirs.push((guest.into(), dir.into()));
+        self
+    }
+
+    /// Buffers the plugin's stdout for [`Plugin::take_stdout`](crate::Plugin::take_stdout).
+    pub fn capture_stdout(mut self) -> Self {
+        self.capture_stdout = true;
+        self
+    }
+
+    /// Buffers the plugin's stderr for [`Plugin::take_stderr`](crate::Plugin::take_stderr).
+    pub fn capture_stderr(mut self) -> Self {
+        self.capture_stderr = true;
+        self
+    }
+
+    /// Creates the WASI environment and adds its imports to `imports`.
+    pub(crate) fn build(
+        &self,
+        store: &mut Store,
+        module: &Module,
+        imports: &mut wasmer::Imports,
+    ) -> anyhow::Result<WasiState> {
+        // WASI's background work runs on this runtime.
+        let handle = crate::runtime::handle();
+        let _runtime = handle.enter();
+        let task_manager = Arc::new(TokioTaskManager::new(handle.clone()));
+        let name = module.name().unwrap_or("plugin").to_owned();
+        let mut builder = WasiEnv::builder(name)
+            .args(&self.args)
+            .envs(self.envs.iter().cloned())
+            .runtime(Arc::new(PluggableRuntime::new(task_manager)));
+        if !self.dirs.is_empty() {
+            // The host filesystem is only reachable through the mapped directories.
+            builder.set_fs(Box::new(virtual_fs::host_fs::FileSystem::new(
+                handle.clone(),
+            )));
+        }
+        for (guest, dir) in &self.dirs {
+            builder.add_map_dir(guest, dir)?;
+        }
+
+        let capture = |enabled: bool| {
+            enabled.then(|| {
+                let (plugin, host) = Pipe::channel();
+                (Box::new(plugin), host)
+            })
+        };
+        let (stdout, stderr) = (capture(self.capture_stdout), capture(self.capture_stderr));
+        if let Some((plugin, _)) = &stdout {
+            builder.set_stdout(plugin.clone());
+        }
+        if let Some((plugin, _)) = &stderr {
+            builder.set_stderr(plugin.clone());
+        }
+
+        let env = builder.finalize(store)?;
+        let wasi_imports = env.import_object(store, module)?;
+        let user_imports = std::mem::replace(imports, wasi_imports);
+        imports.extend(&user_imports);
+
+        Ok(WasiState {
+            env,
+            handle,
+            stdout: stdout.map(|(_, host)| host),
+            stderr: stderr.map(|(_, host)| host),
+        })
+    }
+}
+
+/// The WASI environment of a running plugin.
+pub(crate) struct WasiState {
+    env: WasiFunctionEnv,
+    handle: Handle,
+    stdout: Option<Pipe>,
+    stderr: Option<Pipe>,
+}
+
+impl WasiState {
+    pub(crate) fn initialize(
+        &mut self,
+        store: &mut Store,
+        instance: &Instance,
+    ) -> anyhow::Result<()> {
+        let _runtime = self.handle.enter();
+        Ok(self.env.initialize(store, instance.clone())?)
+    }
+
+    /// Makes the tokio runtime WASI calls rely on available to the current
+    /// thread.
+    pub(crate) fn enter(&self) -> EnterGuard<'_> {
+        self.handle.enter()
+    }
+
+    pub(crate) fn take_stdout(&self) -> Vec<u8> {
+        self.stdout.clone().as_mut().map(drain).unwrap_or_default()
+    }
+
+    pub(crate) fn take_stderr(&self) -> Vec<u8> {
+        self.stderr.clone().as_mut().map(drain).unwrap_or_default()
+    }
+
+    pub(crate) fn cleanup(&self, store: &mut Store) {
+        let _runtime = self.enter();
+        self.env.cleanup(store, None);
+    }
+}
+
+fn drain(pipe: &mut Pipe) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    while let Some(n @ 1..) = pipe.try_read(&mut buf) {
+        output.extend_from_slice(&buf[..n]);
+    }
+    output
+}